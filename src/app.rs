@@ -1,14 +1,26 @@
-use crate::dns_utils::DnsTestResult;
-use crate::mirror_utils::{Distro, Mirror, detect_distro, MirrorTestResult};
+use crate::dns_utils::{DnsServer, DnsTestResult};
+use crate::file_loader::CatalogSource;
+use crate::mirror_apply::{DiffLine, FileChange};
+use crate::mirror_utils::{DetectedSystem, Distro, Mirror, MirrorOrigin, detect_system, MirrorTestResult};
+use crate::sys_dns::{DnsRoute, DnsRouting, NetTarget};
+use std::collections::HashMap;
 use std::net::IpAddr;
+use std::path::PathBuf;
 use tui_input::Input;
 use tokio::sync::mpsc;
 
+const EXPORT_DIR: &str = "dns-master-export";
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum AppState {
     Input,
     Testing,
     Results,
+    PickInterface,
+    PickDistro,
+    EditMirror,
+    SplitDns,
+    ApplyPreview,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -21,12 +33,50 @@ pub enum AppMode {
 pub enum TestTarget {
     Dns(IpAddr),
     Mirror(Mirror),
+    /// Check that the system now resolves through a just-applied server
+    Verify(IpAddr),
+    /// Check which servers answer DNS-over-TLS, for the config export
+    Dot(Vec<DnsServer>),
 }
 
 #[derive(Debug, Clone)]
 pub enum TestResult {
     Dns(DnsTestResult),
-    Mirror(MirrorTestResult),
+    Mirror(Box<MirrorTestResult>),
+    Verified(IpAddr, Result<crate::dns_utils::ResolverCheck, String>),
+    /// The servers that passed the DNS-over-TLS check
+    Dot(Vec<IpAddr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MirrorField {
+    #[default]
+    Name,
+    Url,
+    Category,
+}
+
+/// The add/edit mirror form (Mirror mode)
+#[derive(Default)]
+pub struct MirrorForm {
+    pub name: Input,
+    pub url: Input,
+    // Index into Distro::CATEGORIES
+    pub category: usize,
+    pub field: MirrorField,
+    // Name of the mirror being edited; None when adding one
+    pub editing: Option<String>,
+}
+
+impl MirrorForm {
+    /// The text input with focus, if the focused field is one
+    pub fn focused_input(&mut self) -> Option<&mut Input> {
+        match self.field {
+            MirrorField::Name => Some(&mut self.name),
+            MirrorField::Url => Some(&mut self.url),
+            MirrorField::Category => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -41,6 +91,10 @@ pub struct App {
     pub mode: AppMode,
     pub state: AppState,
     pub dns_servers: Vec<IpAddr>,
+    // Catalog details (name, provider, tags...) of the listed DNS servers
+    pub dns_catalog: HashMap<IpAddr, DnsServer>,
+    // Only test DNS servers with this tag
+    pub tag_filter: Option<String>,
     pub mirrors: Vec<Mirror>,
     pub input: Input,
     pub results: Vec<DnsTestResult>,
@@ -56,7 +110,36 @@ pub struct App {
     pub error_message: Option<String>,
     pub status_message: Option<(String, bool)>, // (message, is_error)
     pub detected_distro: Distro,
+    pub system: DetectedSystem,
+    // Mirror catalog layers below the user's own edits
+    pub mirror_sources: Vec<CatalogSource>,
+    // Where the DNS server list came from, for the input screen
+    pub dns_sources: Vec<String>,
+    // Mirrors imported from upstream lists, kept across reloads
+    pub imported_mirrors: Vec<Mirror>,
+    pub distro_index: usize,
+    // The user's mirror additions/edits/removals, layered over mirror_sources
+    pub user_mirrors_path: Option<PathBuf>,
+    // Highlighted row of the mirror list, and the add/edit form
+    pub mirror_index: usize,
+    pub mirror_form: MirrorForm,
     pub tick_count: u64,
+    // Interface/connection that "apply" changes (None = auto-detect)
+    pub selected_interface: Option<String>,
+    pub interfaces: Vec<NetTarget>,
+    pub interface_index: usize,
+    // Highlighted row of the DNS results table
+    pub results_index: usize,
+    pub split_dns: DnsRouting,
+    // Mirror config rewrite waiting for confirmation
+    pub pending_changes: Vec<FileChange>,
+    /// Line diff of each pending change, computed once when it is planned
+    pub pending_diffs: Vec<Vec<DiffLine>>,
+    pub preview_scroll: u16,
+    // Restart dockerd after writing daemon.json
+    pub restart_docker: bool,
+    // Upstream sync times (unix seconds) that mirror lag is measured against
+    pub upstream_synced: HashMap<Distro, u64>,
     // Concurrency
     pub tx: Option<mpsc::Sender<TestTarget>>,
     pub rx: Option<mpsc::Receiver<TestResult>>,
@@ -64,11 +147,13 @@ pub struct App {
 
 impl Default for App {
     fn default() -> Self {
-        let distro = detect_distro();
+        let system = detect_system();
         Self {
             mode: AppMode::Dns,
             state: AppState::Input,
             dns_servers: Vec::new(),
+            dns_catalog: HashMap::new(),
+            tag_filter: None,
             mirrors: Vec::new(),
             input: Input::default(),
             results: Vec::new(),
@@ -83,8 +168,26 @@ impl Default for App {
             should_quit: false,
             error_message: None,
             status_message: None,
-            detected_distro: distro,
+            detected_distro: system.family.clone(),
+            system,
+            mirror_sources: vec![CatalogSource::Builtin],
+            dns_sources: Vec::new(),
+            imported_mirrors: Vec::new(),
+            distro_index: 0,
+            user_mirrors_path: crate::file_loader::user_mirrors_path(),
+            mirror_index: 0,
+            mirror_form: MirrorForm::default(),
             tick_count: 0,
+            selected_interface: None,
+            interfaces: Vec::new(),
+            interface_index: 0,
+            results_index: 0,
+            split_dns: DnsRouting::default(),
+            pending_changes: Vec::new(),
+            pending_diffs: Vec::new(),
+            preview_scroll: 0,
+            restart_docker: false,
+            upstream_synced: HashMap::new(),
             tx: None,
             rx: None,
         }
@@ -96,9 +199,10 @@ impl App {
         self.tick_count = self.tick_count.wrapping_add(1);
     }
     pub fn new(initial_dns: Vec<IpAddr>) -> Self {
-        let mut app = Self::default();
-        app.dns_servers = initial_dns;
-        app
+        Self {
+            dns_servers: initial_dns,
+            ..Self::default()
+        }
     }
 
     /// Add a DNS server to the list
@@ -123,9 +227,58 @@ impl App {
     }
 
 
+    /// Remember the catalog details of each DNS server
+    pub fn set_dns_catalog(&mut self, servers: Vec<DnsServer>) {
+        for server in servers {
+            match self.dns_catalog.get_mut(&server.ip) {
+                Some(existing) => existing.merge(server),
+                None => {
+                    self.dns_catalog.insert(server.ip, server);
+                }
+            }
+        }
+    }
+
+    /// Catalog name of a DNS server, if it has one
+    pub fn dns_label(&self, ip: &IpAddr) -> Option<String> {
+        self.dns_catalog.get(ip).and_then(DnsServer::label)
+    }
+
+    /// Tags used by the listed DNS servers, sorted
+    pub fn dns_tags(&self) -> Vec<String> {
+        let mut tags: Vec<String> = self.dns_servers.iter()
+            .filter_map(|ip| self.dns_catalog.get(ip))
+            .flat_map(|s| s.tags.iter().cloned())
+            .collect();
+        tags.sort();
+        tags.dedup();
+        tags
+    }
+
+    /// Step the tag filter through the catalog's tags, then back to all servers
+    pub fn cycle_tag_filter(&mut self) {
+        let tags = self.dns_tags();
+        let next = match &self.tag_filter {
+            None => 0,
+            Some(current) => tags.iter().position(|t| t == current).map_or(tags.len(), |i| i + 1),
+        };
+        self.tag_filter = tags.get(next).cloned();
+    }
+
+    /// The DNS servers a test run covers: the list, narrowed by the tag filter
+    pub fn dns_targets(&self) -> Vec<IpAddr> {
+        let Some(tag) = &self.tag_filter else {
+            return self.dns_servers.clone();
+        };
+        self.dns_servers.iter()
+            .filter(|ip| self.dns_catalog.get(ip).is_some_and(|s| s.has_tag(tag)))
+            .copied()
+            .collect()
+    }
+
     /// Get the next DNS server to test
     pub fn get_current_test_target(&self) -> Option<IpAddr> {
-        self.dns_servers.get(self.testing_index).copied()
+        self.dns_targets().get(self.testing_index).copied()
     }
 
     /// Record a test result and advance to the next server
@@ -158,7 +311,7 @@ impl App {
         self.results.push(result);
         self.testing_index += 1;
 
-        if self.testing_index >= self.dns_servers.len() {
+        if self.testing_index >= self.dns_targets().len() {
             self.finish_testing();
         }
     }
@@ -167,6 +320,11 @@ impl App {
     fn finish_testing(&mut self) {
         self.state = AppState::Results;
         self.sort_results();
+        if self.mode == AppMode::Dns
+            && let Err(e) = crate::dns_utils::BenchmarkState::record_results(&self.results)
+        {
+            self.status_message = Some((format!("Could not save results for the watchdog: {}", e), true));
+        }
     }
 
 
@@ -185,6 +343,12 @@ impl App {
                     let cmp = a.latency.cmp(&b.latency);
                     if ascending { cmp } else { cmp.reverse() }
                 });
+                // Time to first byte for mirrors
+                self.mirror_results.sort_by(|a, b| {
+                    let ttfb = |r: &MirrorTestResult| r.timings.as_ref().map(|t| t.ttfb);
+                    let cmp = ttfb(a).cmp(&ttfb(b));
+                    if ascending { cmp } else { cmp.reverse() }
+                });
             }
             SortColumn::DownloadSpeed => {
                 if self.mode == AppMode::Dns {
@@ -196,12 +360,13 @@ impl App {
                         if ascending { cmp } else { cmp.reverse() }
                     });
                 } else {
+                    // Stale mirrors stay below fresh ones either way, as when applying
                     self.mirror_results.sort_by(|a, b| {
                         let cmp = a
                             .speed_mbps
                             .partial_cmp(&b.speed_mbps)
                             .unwrap_or(std::cmp::Ordering::Equal);
-                        if ascending { cmp } else { cmp.reverse() }
+                        a.is_stale().cmp(&b.is_stale()).then(if ascending { cmp } else { cmp.reverse() })
                     });
                 }
             }
@@ -226,7 +391,7 @@ impl App {
                     SortColumn::Ip
                 }
             }
-            SortColumn::Name => SortColumn::Ip,
+            SortColumn::Name => SortColumn::Latency,
         };
         self.sort_results();
     }
@@ -249,15 +414,21 @@ impl App {
         self.best_result = None;
         self.best_mirror_result = None;
         self.testing_index = 0;
+        self.results_index = 0;
         self.status_message = None;
     }
 
     /// Apply the fastest DNS to the system
     pub fn apply_fastest_dns(&mut self) {
         if let Some(best) = &self.best_result {
-            match crate::sys_dns::set_system_dns(best.ip) {
+            match crate::sys_dns::set_system_dns(best.ip, self.selected_interface.as_deref()) {
                 Ok(_) => {
-                    self.status_message = Some((format!("Successfully set system DNS to {}", best.ip), false));
+                    let _ = crate::dns_utils::BenchmarkState::record_applied(best.ip);
+                    // The worker reports back through `update`
+                    let verifying = self.tx.as_ref()
+                        .is_some_and(|tx| tx.try_send(TestTarget::Verify(best.ip)).is_ok());
+                    let note = if verifying { ", verifying..." } else { "" };
+                    self.status_message = Some((format!("Set system DNS to {}{}{}", best.ip, self.applied_on(), note), false));
                 }
                 Err(e) => {
                     self.status_message = Some((format!("Failed to set system DNS: {}", e), true));
@@ -268,25 +439,424 @@ impl App {
         }
     }
 
-    /// Record a mirror test result
-    pub fn record_mirror_result(&mut self, result: MirrorTestResult) {
-        self.last_mirror_result = Some(result.clone());
+    /// Open the interface picker with the currently available interfaces
+    pub fn open_interface_picker(&mut self) {
+        self.interfaces = crate::sys_dns::list_targets();
+        if self.interfaces.is_empty() {
+            self.status_message = Some(("No network interfaces found.".to_string(), true));
+            return;
+        }
+        self.interface_index = self.selected_interface.as_ref()
+            .and_then(|name| self.interfaces.iter().position(|t| &t.name == name))
+            .unwrap_or(0);
+        self.state = AppState::PickInterface;
+    }
 
-        // Update best mirror result (higher speed is better)
-        if let Some(best) = &self.best_mirror_result {
-            let is_better = match (result.speed_mbps, best.speed_mbps) {
-                (Some(s1), Some(s2)) => s1 > s2,
-                (Some(_), None) => true,
-                _ => false,
+    /// Move the interface picker cursor
+    pub fn move_interface_selection(&mut self, down: bool) {
+        let len = self.interfaces.len();
+        if len == 0 {
+            return;
+        }
+        self.interface_index = if down {
+            (self.interface_index + 1) % len
+        } else {
+            (self.interface_index + len - 1) % len
+        };
+    }
+
+    /// Use the highlighted interface and remember it for this network
+    pub fn confirm_interface(&mut self) {
+        if let Some(target) = self.interfaces.get(self.interface_index) {
+            let name = target.name.clone();
+            self.status_message = match crate::sys_dns::remember_target(&name) {
+                Ok(_) => Some((format!("DNS will be applied to {} on this network", name), false)),
+                Err(e) => Some((format!("Using {} (not remembered: {})", name, e), true)),
             };
-            if is_better {
-                self.best_mirror_result = Some(result.clone());
+            self.selected_interface = Some(name);
+        }
+        self.state = AppState::Results;
+    }
+
+    /// Close the interface picker without changing the selection
+    pub fn cancel_interface_picker(&mut self) {
+        self.state = AppState::Results;
+    }
+
+    /// Prepare mirrors for `distro` instead of the detected one and reload the list
+    pub fn set_target_distro(&mut self, distro: Distro) {
+        self.detected_distro = distro;
+        self.load_mirrors();
+    }
+
+    /// (Re)load the mirror list for the target distro and its global services
+    pub fn load_mirrors(&mut self) {
+        match crate::file_loader::load_mirrors(&self.loaded_mirror_sources(), self.detected_distro.clone(), &self.system) {
+            Ok(mirrors) => {
+                self.mirrors = mirrors;
+                self.mirrors.extend(self.imported_mirrors.iter().cloned());
+                self.status_message = None;
+                self.add_current_mirrors();
+                self.mirror_index = self.mirror_index.min(self.mirrors.len().saturating_sub(1));
+            }
+            Err(e) => {
+                self.mirrors.clear();
+                self.status_message = Some((format!("Failed to load mirrors: {:#}", e), true));
             }
-        } else if result.error.is_none() && result.speed_mbps.is_some() {
-            self.best_mirror_result = Some(result.clone());
         }
+    }
+
+    /// The catalog layers mirrors are loaded from, including the user's edits once they exist
+    pub fn loaded_mirror_sources(&self) -> Vec<CatalogSource> {
+        let mut sources = self.mirror_sources.clone();
+        if let Some(user_path) = self.user_mirrors_path.as_ref().filter(|p| p.exists()) {
+            sources.push(CatalogSource::File(user_path.clone()));
+        }
+        sources
+    }
+
+    /// Add the mirrors the system is configured with as a baseline, tagging
+    /// listed mirrors that are already in use instead of testing them twice
+    fn add_current_mirrors(&mut self) {
+        let current = crate::file_loader::load_current_mirrors(
+            std::path::Path::new("/"),
+            &self.detected_distro,
+            &self.mirrors,
+            &self.system,
+        );
+        let mut unlisted = Vec::new();
+        for mirror in current.mirrors {
+            match self.mirrors.iter_mut().find(|m| m.same_root(&mirror)) {
+                Some(listed) => listed.origin = MirrorOrigin::Current,
+                None => unlisted.push(mirror),
+            }
+        }
+        self.mirrors.splice(0..0, unlisted);
+        if !current.unreadable.is_empty() {
+            let message = format!("Failed to read current mirrors: {}", current.unreadable.join("; "));
+            self.status_message = Some((message, true));
+        }
+    }
+
+    /// Move the highlighted row of the mirror list
+    pub fn move_mirror_selection(&mut self, down: bool) {
+        let len = self.mirrors.len();
+        if len == 0 {
+            return;
+        }
+        self.mirror_index = if down {
+            (self.mirror_index + 1) % len
+        } else {
+            (self.mirror_index + len - 1) % len
+        };
+    }
+
+    /// Open the mirror form, empty or (with `edit`) filled from the highlighted mirror
+    pub fn open_mirror_form(&mut self, edit: bool) {
+        let mut form = MirrorForm {
+            category: Distro::CATEGORIES.iter().position(|d| *d == self.detected_distro).unwrap_or(0),
+            ..MirrorForm::default()
+        };
+        if edit {
+            let Some(mirror) = self.mirrors.get(self.mirror_index) else {
+                return;
+            };
+            if mirror.origin == MirrorOrigin::Imported {
+                self.status_message = Some(("Imported mirrors come from --import and can't be edited here.".to_string(), true));
+                return;
+            }
+            form.name = Input::new(mirror.name.clone());
+            form.url = Input::new(mirror.base_url.clone());
+            form.category = Distro::CATEGORIES.iter().position(|d| *d == mirror.distro).unwrap_or(form.category);
+            form.editing = Some(mirror.name.clone());
+        }
+        self.mirror_form = form;
+        self.status_message = None;
+        self.state = AppState::EditMirror;
+    }
+
+    /// Move focus to the next (or previous) form field
+    pub fn next_mirror_field(&mut self, forward: bool) {
+        let fields = [MirrorField::Name, MirrorField::Url, MirrorField::Category];
+        let i = fields.iter().position(|f| *f == self.mirror_form.field).unwrap_or(0);
+        let next = if forward { i + 1 } else { i + fields.len() - 1 };
+        self.mirror_form.field = fields[next % fields.len()];
+    }
+
+    /// Step the form's category through the distros and services
+    pub fn cycle_mirror_category(&mut self, forward: bool) {
+        let len = Distro::CATEGORIES.len();
+        let i = self.mirror_form.category;
+        self.mirror_form.category = if forward { (i + 1) % len } else { (i + len - 1) % len };
+    }
+
+    /// Validate the form and save the mirror to the user's mirror file
+    pub fn save_mirror_form(&mut self) {
+        let name = self.mirror_form.name.value().trim().to_string();
+        if name.is_empty() {
+            self.status_message = Some(("Mirror name is empty".to_string(), true));
+            return;
+        }
+        let base_url = match crate::mirror_utils::parse_mirror_url(self.mirror_form.url.value()) {
+            Ok(url) => url,
+            Err(e) => {
+                self.status_message = Some((e.to_string(), true));
+                return;
+            }
+        };
+        let Some(user_path) = self.user_mirrors_path.clone() else {
+            self.status_message = Some(("Could not determine the config directory".to_string(), true));
+            return;
+        };
+        let distro = Distro::CATEGORIES[self.mirror_form.category].clone();
+        let record = crate::file_loader::MirrorRecord {
+            name: name.clone(),
+            base_url: Some(base_url),
+            probe: None,
+            url: None,
+            distro: distro.as_str().to_string(),
+            removed: false,
+        };
+        let replaces = self.mirror_form.editing.clone();
+        match crate::file_loader::save_user_mirror(&user_path, &self.mirror_sources, replaces.as_deref(), record) {
+            Ok(()) => {
+                self.load_mirrors();
+                self.state = AppState::Input;
+                let shown = distro == self.detected_distro || distro.is_global_service();
+                let msg = if shown {
+                    format!("Saved {} to {}", name, user_path.display())
+                } else {
+                    format!("Saved {} to {} (hidden while targeting {})", name, user_path.display(), self.detected_distro.as_str())
+                };
+                self.mirror_index = self.mirrors.iter().position(|m| m.name == name).unwrap_or(0);
+                self.status_message = Some((msg, false));
+            }
+            Err(e) => self.status_message = Some((format!("Failed to save mirror: {:#}", e), true)),
+        }
+    }
+
+    /// Close the mirror form without saving
+    pub fn cancel_mirror_form(&mut self) {
+        self.status_message = None;
+        self.state = AppState::Input;
+    }
+
+    /// Remove the highlighted mirror from the user's mirror list
+    pub fn remove_selected_mirror(&mut self) {
+        let Some(mirror) = self.mirrors.get(self.mirror_index) else {
+            return;
+        };
+        if mirror.origin == MirrorOrigin::Imported {
+            self.status_message = Some(("Imported mirrors come from --import and can't be removed here.".to_string(), true));
+            return;
+        }
+        let Some(user_path) = self.user_mirrors_path.clone() else {
+            self.status_message = Some(("Could not determine the config directory".to_string(), true));
+            return;
+        };
+        let name = mirror.name.clone();
+        match crate::file_loader::remove_user_mirror(&user_path, &self.mirror_sources, &name) {
+            Ok(()) => {
+                self.load_mirrors();
+                if self.mirrors.iter().any(|m| m.name == name) {
+                    // Still there: it is the system's own config, re-added as the baseline
+                    self.status_message = Some((format!("{} is configured on this system and stays as the baseline", name), true));
+                } else {
+                    self.status_message = Some((format!("Removed {}", name), false));
+                }
+                self.mirror_index = self.mirror_index.min(self.mirrors.len().saturating_sub(1));
+            }
+            Err(e) => self.status_message = Some((format!("Failed to remove mirror: {:#}", e), true)),
+        }
+    }
+
+    /// Open the target distro picker (Mirror mode)
+    pub fn open_distro_picker(&mut self) {
+        self.distro_index = Distro::TARGETS.iter().position(|d| *d == self.detected_distro).unwrap_or(0);
+        self.state = AppState::PickDistro;
+    }
 
+    /// Move the distro picker cursor
+    pub fn move_distro_selection(&mut self, down: bool) {
+        let len = Distro::TARGETS.len();
+        self.distro_index = if down {
+            (self.distro_index + 1) % len
+        } else {
+            (self.distro_index + len - 1) % len
+        };
+    }
+
+    /// Switch to the highlighted distro
+    pub fn confirm_distro(&mut self) {
+        self.state = AppState::Input;
+        self.set_target_distro(Distro::TARGETS[self.distro_index].clone());
+    }
+
+    /// Close the distro picker without changing the target
+    pub fn cancel_distro_picker(&mut self) {
+        self.state = AppState::Input;
+    }
+
+    /// Move the highlighted row of the DNS results table
+    pub fn move_result_selection(&mut self, down: bool) {
+        let len = self.results.len();
+        if len == 0 {
+            return;
+        }
+        self.results_index = if down {
+            (self.results_index + 1) % len
+        } else {
+            (self.results_index + len - 1) % len
+        };
+    }
+
+    /// Open the split DNS editor; the fastest server handles everything not routed
+    pub fn open_split_dns_editor(&mut self) {
+        if self.results.is_empty() {
+            self.status_message = Some(("No test results to pick resolvers from.".to_string(), true));
+            return;
+        }
+        if self.split_dns.default.is_none() {
+            self.split_dns.default = self.best_result.as_ref().map(|r| r.ip);
+        }
+        self.input.reset();
+        self.status_message = None;
+        self.state = AppState::SplitDns;
+    }
+
+    /// Route the domain typed in the input to the highlighted resolver.
+    /// With an empty input, make the highlighted resolver the default instead.
+    pub fn add_split_dns_route(&mut self) {
+        let Some(server) = self.results.get(self.results_index).map(|r| r.ip) else {
+            return;
+        };
+        let entry = self.input.value().trim().to_string();
+        if entry.is_empty() {
+            self.split_dns.default = Some(server);
+            self.status_message = Some((format!("{} now handles all other domains", server), false));
+            return;
+        }
+        match DnsRoute::parse(&entry, server) {
+            Ok(route) => {
+                self.split_dns.routes.retain(|r| r.domain != route.domain);
+                self.status_message = Some((format!("Routing {} to {}", route.domain, server), false));
+                self.split_dns.routes.push(route);
+                self.input.reset();
+            }
+            Err(e) => self.status_message = Some((e.to_string(), true)),
+        }
+    }
+
+    /// Delete the most recently added split DNS route
+    pub fn remove_last_split_dns_route(&mut self) {
+        self.split_dns.routes.pop();
+    }
+
+    /// Apply the split DNS routing table to the system
+    pub fn apply_split_dns(&mut self) {
+        if self.split_dns.default.is_none() && self.split_dns.routes.is_empty() {
+            self.status_message = Some(("Nothing to apply: add a route first.".to_string(), true));
+            return;
+        }
+        self.status_message = match crate::sys_dns::apply_split_dns(&self.split_dns) {
+            Ok(_) => Some((format!("Applied {} split DNS route(s)", self.split_dns.routes.len()), false)),
+            Err(e) => Some((format!("Failed to apply split DNS: {}", e), true)),
+        };
+    }
+
+    /// Leave the split DNS editor
+    pub fn close_split_dns_editor(&mut self) {
+        self.input.reset();
+        self.state = AppState::Results;
+    }
+
+    /// Write resolver config snippets for the ranked results to `./dns-master-export`
+    pub fn export_configs(&mut self) {
+        if self.mode != AppMode::Dns {
+            return;
+        }
+        let ranked = self.ranked_catalog_servers();
+        if ranked.is_empty() {
+            self.status_message = Some(("No working servers to export.".to_string(), true));
+            return;
+        }
+
+        // DNS-over-TLS is checked on the worker first; `update` writes the files
+        let dot: Vec<DnsServer> = ranked.into_iter().filter(|s| s.dot_name().is_some()).collect();
+        if !dot.is_empty() && self.tx.as_ref().is_some_and(|tx| tx.try_send(TestTarget::Dot(dot)).is_ok()) {
+            self.status_message = Some(("Checking DNS-over-TLS before exporting...".to_string(), false));
+        } else {
+            self.write_exports(&[]);
+        }
+    }
+
+    fn ranked_catalog_servers(&self) -> Vec<DnsServer> {
+        crate::dns_utils::ranked_servers(&self.results)
+            .into_iter()
+            .map(|ip| self.dns_catalog.get(&ip).cloned().unwrap_or_else(|| DnsServer::new(ip)))
+            .collect()
+    }
+
+    /// Write every export format to `EXPORT_DIR`; `dot` are the servers that passed
+    /// the DNS-over-TLS check.
+    fn write_exports(&mut self, dot: &[IpAddr]) {
+        use crate::config_export::{render, ExportFormat};
+
+        let ranked = self.ranked_catalog_servers();
+        let dir = std::path::Path::new(EXPORT_DIR);
+        let written = std::fs::create_dir_all(dir).and_then(|_| {
+            for format in ExportFormat::ALL {
+                std::fs::write(dir.join(format.file_name()), render(format, &ranked, dot))?;
+            }
+            Ok(())
+        });
+        self.status_message = match written {
+            Ok(_) => Some((format!("Wrote resolved.conf, dnsmasq.conf, unbound.conf and resolv.conf to ./{}", EXPORT_DIR), false)),
+            Err(e) => Some((format!("Failed to export configs: {}", e), true)),
+        };
+    }
+
+    /// " on <interface>" when DNS was applied to a chosen interface
+    fn applied_on(&self) -> String {
+        self.selected_interface.as_deref()
+            .map(|i| format!(" on {}", i))
+            .unwrap_or_default()
+    }
+
+    /// Report the worker's check that the system really resolves through `ip`.
+    fn record_verification(&mut self, ip: IpAddr, check: Result<crate::dns_utils::ResolverCheck, String>) {
+        let on = self.applied_on();
+        let list = |addrs: &[IpAddr]| addrs.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(", ");
+        let message = match check {
+            Ok(c) if c.passed => (
+                format!("Set system DNS to {}{} ✔ verified: queries egress via {}", ip, on, list(&c.via_system)),
+                false,
+            ),
+            Ok(c) => (
+                format!(
+                    "Set system DNS to {}{}, but queries still egress via {} (expected {}). A VPN or DoH may override it.",
+                    ip, on, list(&c.via_system), list(&c.via_expected)
+                ),
+                true,
+            ),
+            Err(e) => (format!("Set system DNS to {}{}, but could not verify it: {}", ip, on, e), true),
+        };
+        self.status_message = Some(message);
+    }
+
+    /// Record a mirror test result
+    pub fn record_mirror_result(&mut self, result: MirrorTestResult) {
+        self.last_mirror_result = Some(result.clone());
         self.mirror_results.push(result);
+        crate::mirror_freshness::update_lags(&mut self.mirror_results, &self.upstream_synced);
+
+        // Lags shift as newer mirrors come in, so re-pick the best with the apply ranking
+        self.best_mirror_result = self
+            .mirror_results
+            .iter()
+            .filter(|r| r.error.is_none() && r.speed_mbps.is_some())
+            .min_by(|a, b| crate::mirror_utils::rank_order(a, b))
+            .cloned();
         self.testing_index += 1;
 
         if self.testing_index >= self.mirrors.len() {
@@ -295,6 +865,107 @@ impl App {
     }
 
 
+    /// Plan the system mirror config for the ranked results and show the diff
+    pub fn plan_mirror_apply(&mut self) {
+        // The picked target only says which mirrors to test; the files on / belong to this system
+        if self.detected_distro != self.system.family {
+            self.status_message = Some((
+                format!(
+                    "Targeting {}, but this system is {}: not writing {} mirror configs to it.",
+                    self.detected_distro.as_str(),
+                    self.system.family.as_str(),
+                    self.detected_distro.as_str()
+                ),
+                true,
+            ));
+            return;
+        }
+        let root = std::path::Path::new("/");
+        match crate::mirror_apply::plan_for_distro(root, &self.detected_distro, &self.mirror_results) {
+            Ok(changes) => self.preview_changes(changes),
+            Err(e) => self.status_message = Some((e.to_string(), true)),
+        }
+    }
+
+    /// Plan pip/npm/cargo/Go configs for the fastest registry mirrors and show the diff
+    pub fn plan_ecosystem_apply(&mut self) {
+        let Some(home) = std::env::var_os("HOME").map(std::path::PathBuf::from) else {
+            self.status_message = Some(("HOME is not set".to_string(), true));
+            return;
+        };
+        match crate::mirror_apply::plan_ecosystem_configs(&home, &self.mirror_results) {
+            Ok(changes) => self.preview_changes(changes),
+            Err(e) => self.status_message = Some((e.to_string(), true)),
+        }
+    }
+
+    /// Plan merging the working Docker registry mirrors into daemon.json and show the diff
+    pub fn plan_docker_apply(&mut self) {
+        let root = std::path::Path::new("/");
+        match crate::mirror_apply::plan_docker_daemon_json(root, &self.mirror_results) {
+            Ok(change) => self.preview_changes(vec![change]),
+            Err(e) => self.status_message = Some((e.to_string(), true)),
+        }
+    }
+
+    /// Show the diff preview for `changes`
+    fn preview_changes(&mut self, changes: Vec<FileChange>) {
+        self.pending_diffs = changes.iter().map(FileChange::diff).collect();
+        self.pending_changes = changes;
+        self.preview_scroll = 0;
+        self.state = AppState::ApplyPreview;
+    }
+
+    /// Write the previewed changes
+    pub fn confirm_apply(&mut self) {
+        use crate::mirror_apply::{apply_changes, restart_docker, DOCKER_RESTART_HINT};
+
+        let changes = std::mem::take(&mut self.pending_changes);
+        self.pending_diffs.clear();
+        self.state = AppState::Results;
+        let backups = match apply_changes(&changes) {
+            Ok(backups) => backups,
+            Err(e) => {
+                self.status_message = Some((format!("Failed to apply mirrors: {:#}", e), true));
+                return;
+            }
+        };
+
+        let files = changes.iter().map(|c| c.path.display().to_string()).collect::<Vec<_>>().join(", ");
+        let mut message = format!("Wrote {}", files);
+        if !backups.is_empty() {
+            message.push_str(&format!(" (backup: {})", backups.iter().map(|b| b.display().to_string()).collect::<Vec<_>>().join(", ")));
+        }
+
+        if changes.iter().any(|c| c.path.ends_with("docker/daemon.json")) {
+            if !self.restart_docker {
+                message.push_str(&format!(". Run `{}` to use them.", DOCKER_RESTART_HINT));
+            } else if let Err(e) = restart_docker() {
+                self.status_message = Some((format!("{}, but restarting Docker failed: {}", message, e), true));
+                return;
+            } else {
+                message.push_str(". Docker restarted.");
+            }
+        }
+        self.status_message = Some((message, false));
+    }
+
+    /// Discard the previewed changes
+    pub fn cancel_apply(&mut self) {
+        self.pending_changes.clear();
+        self.pending_diffs.clear();
+        self.state = AppState::Results;
+    }
+
+    /// Scroll the diff preview
+    pub fn scroll_preview(&mut self, down: bool) {
+        self.preview_scroll = if down {
+            self.preview_scroll.saturating_add(1)
+        } else {
+            self.preview_scroll.saturating_sub(1)
+        };
+    }
+
     /// Toggle between DNS and Mirror mode
     pub fn toggle_mode(&mut self) {
         if self.state == AppState::Input {
@@ -309,40 +980,50 @@ impl App {
     /// Start testing
     pub fn start_testing(&mut self) {
         let targets = match self.mode {
-            AppMode::Dns => self.dns_servers.iter().map(|ip| TestTarget::Dns(*ip)).collect::<Vec<_>>(),
+            AppMode::Dns => self.dns_targets().into_iter().map(TestTarget::Dns).collect::<Vec<_>>(),
             AppMode::Mirror => self.mirrors.iter().map(|m| TestTarget::Mirror(m.clone())).collect::<Vec<_>>(),
         };
 
-        if !targets.is_empty() {
+        if targets.is_empty() {
+            if let (AppMode::Dns, Some(tag)) = (self.mode, &self.tag_filter) {
+                self.error_message = Some(format!("No servers in the list are tagged '{}'", tag));
+            }
+        } else {
             self.state = AppState::Testing;
             self.testing_index = 0;
             self.results.clear();
             self.mirror_results.clear();
             
             // Send the first task
-            if let Some(tx) = &self.tx {
-                if let Some(target) = targets.get(0).cloned() {
-                    let _ = tx.try_send(target);
-                }
+            if let Some(tx) = &self.tx
+                && let Some(target) = targets.first().cloned()
+            {
+                let _ = tx.try_send(target);
             }
         }
     }
 
     /// Process updates (check for results)
     pub fn update(&mut self) {
-        if self.state != AppState::Testing {
-            return;
-        }
-
-        while let Ok(result) = self.rx.as_mut().unwrap().try_recv() {
+        while let Some(Ok(result)) = self.rx.as_mut().map(|rx| rx.try_recv()) {
             match result {
+                TestResult::Verified(ip, check) => {
+                    self.record_verification(ip, check);
+                    continue;
+                }
+                TestResult::Dot(dot) => {
+                    self.write_exports(&dot);
+                    continue;
+                }
+                _ if self.state != AppState::Testing => continue,
                 TestResult::Dns(res) => self.record_result(res),
-                TestResult::Mirror(res) => self.record_mirror_result(res),
+                TestResult::Mirror(res) => self.record_mirror_result(*res),
             }
 
             // Check if we reached the end
+            let dns_targets = self.dns_targets();
             let total = match self.mode {
-                AppMode::Dns => self.dns_servers.len(),
+                AppMode::Dns => dns_targets.len(),
                 AppMode::Mirror => self.mirrors.len(),
             };
 
@@ -350,7 +1031,7 @@ impl App {
                 // Send next task
                 if let Some(tx) = &self.tx {
                     let target = match self.mode {
-                        AppMode::Dns => TestTarget::Dns(self.dns_servers[self.testing_index]),
+                        AppMode::Dns => TestTarget::Dns(dns_targets[self.testing_index]),
                         AppMode::Mirror => TestTarget::Mirror(self.mirrors[self.testing_index].clone()),
                     };
                     let _ = tx.try_send(target);
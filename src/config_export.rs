@@ -0,0 +1,149 @@
+use crate::dns_utils::DnsServer;
+use std::net::IpAddr;
+
+/// Resolver configuration formats we can generate from benchmark results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// systemd-resolved `resolved.conf`
+    Resolved,
+    /// dnsmasq `server=` lines
+    Dnsmasq,
+    /// unbound `forward-zone`
+    Unbound,
+    /// plain `resolv.conf`
+    ResolvConf,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 4] = [
+        ExportFormat::Resolved,
+        ExportFormat::Dnsmasq,
+        ExportFormat::Unbound,
+        ExportFormat::ResolvConf,
+    ];
+
+    /// File name used when writing the snippet to a directory
+    pub fn file_name(&self) -> &str {
+        match self {
+            ExportFormat::Resolved => "resolved.conf",
+            ExportFormat::Dnsmasq => "dnsmasq.conf",
+            ExportFormat::Unbound => "unbound.conf",
+            ExportFormat::ResolvConf => "resolv.conf",
+        }
+    }
+}
+
+// glibc reads at most three nameserver lines (MAXNS)
+const RESOLV_CONF_MAX: usize = 3;
+
+/// Render a config snippet for `servers`, which must be ordered best first.
+/// `dot` lists the servers that passed a DNS-over-TLS check.
+pub fn render(format: ExportFormat, servers: &[DnsServer], dot: &[IpAddr]) -> String {
+    let header = format!("# Generated by DNS Master from {} tested resolver(s), fastest first\n", servers.len());
+    let mut out = header;
+
+    match format {
+        ExportFormat::Resolved => {
+            // FallbackDNS= is only used when no DNS= is configured at all, so every
+            // working server goes into DNS=, which resolved tries in order
+            out.push_str("# Drop into /etc/systemd/resolved.conf.d/dns-master.conf\n");
+            out.push_str("[Resolve]\n");
+            out.push_str(&format!("DNS={}\n", join(servers)));
+        }
+        ExportFormat::Dnsmasq => {
+            out.push_str("# Drop into /etc/dnsmasq.d/dns-master.conf\n");
+            out.push_str("no-resolv\n");
+            out.push_str("strict-order\n");
+            for server in servers {
+                out.push_str(&format!("server={}\n", server.ip));
+            }
+        }
+        ExportFormat::Unbound => {
+            out.push_str("# Drop into /etc/unbound/unbound.conf.d/dns-master.conf\n");
+            out.push_str("forward-zone:\n");
+            out.push_str("    name: \".\"\n");
+            for server in servers {
+                out.push_str(&format!("    forward-addr: {}\n", server.ip));
+            }
+            // forward-tls-upstream covers the whole zone and would cut off the plain-DNS
+            // servers, so the TLS variant is offered as an alternative to switch to
+            let tls = servers
+                .iter()
+                .filter(|s| dot.contains(&s.ip))
+                .filter_map(|s| Some((s.ip, s.dot_name()?)))
+                .collect::<Vec<_>>();
+            if !tls.is_empty() {
+                out.push_str(&format!(
+                    "\n# {} of these servers passed a DNS-over-TLS check. To forward over TLS to\n\
+                     # them only, replace the zone above with this one and set a CA bundle in server:\n\
+                     #     tls-cert-bundle: /etc/ssl/certs/ca-certificates.crt\n",
+                    tls.len()
+                ));
+                out.push_str("# forward-zone:\n");
+                out.push_str("#     name: \".\"\n");
+                out.push_str("#     forward-tls-upstream: yes\n");
+                for (ip, name) in tls {
+                    out.push_str(&format!("#     forward-addr: {}@853#{}\n", ip, name));
+                }
+            }
+        }
+        ExportFormat::ResolvConf => {
+            for server in servers.iter().take(RESOLV_CONF_MAX) {
+                out.push_str(&format!("nameserver {}\n", server.ip));
+            }
+        }
+    }
+    out
+}
+
+fn join(servers: &[DnsServer]) -> String {
+    servers.iter().map(|s| s.ip.to_string()).collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(ip: &str, protocol: Option<&str>, tls_name: Option<&str>) -> DnsServer {
+        let mut server = DnsServer::new(ip.parse().unwrap());
+        server.protocol = protocol.map(str::to_string);
+        server.tls_name = tls_name.map(str::to_string);
+        server
+    }
+
+    #[test]
+    fn resolved_lists_every_server_in_dns() {
+        let servers = [server("1.1.1.1", None, None), server("9.9.9.9", None, None), server("178.22.122.100", None, None)];
+        let out = render(ExportFormat::Resolved, &servers, &[]);
+        assert!(out.contains("DNS=1.1.1.1 9.9.9.9 178.22.122.100\n"));
+        assert!(!out.contains("FallbackDNS"));
+    }
+
+    #[test]
+    fn unbound_offers_tls_zone_for_tested_dot_servers() {
+        let servers = [
+            server("1.1.1.1", Some("udp;dot;doh"), Some("cloudflare-dns.com")),
+            server("178.22.122.100", Some("udp"), None),
+            server("9.9.9.9", Some("udp;dot;doh"), Some("dns.quad9.net")),
+        ];
+        // 9.9.9.9 claims DoT but failed the check
+        let out = render(ExportFormat::Unbound, &servers, &["1.1.1.1".parse().unwrap()]);
+        assert!(out.contains(
+            "    forward-addr: 1.1.1.1\n    forward-addr: 178.22.122.100\n    forward-addr: 9.9.9.9\n"
+        ));
+        assert!(out.contains("# 1 of these servers passed a DNS-over-TLS check"));
+        assert!(out.contains("#     forward-tls-upstream: yes\n#     forward-addr: 1.1.1.1@853#cloudflare-dns.com\n"));
+        assert!(!out.contains("9.9.9.9@853"));
+        assert!(!out.lines().any(|l| l.starts_with("    forward-tls-upstream")));
+    }
+
+    #[test]
+    fn unbound_stays_plain_without_dot_names() {
+        // Speaking DoT is not enough: without a TLS name unbound cannot check the certificate
+        let servers = [server("1.1.1.1", Some("udp;dot"), None), server("8.8.8.8", None, Some("dns.google"))];
+        let dot = ["1.1.1.1".parse().unwrap(), "8.8.8.8".parse().unwrap()];
+        let out = render(ExportFormat::Unbound, &servers, &dot);
+        assert!(!out.contains("forward-tls-upstream"));
+        assert!(out.contains("    forward-addr: 1.1.1.1\n    forward-addr: 8.8.8.8\n"));
+    }
+}
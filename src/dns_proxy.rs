@@ -0,0 +1,489 @@
+use anyhow::{Context, Result};
+use hickory_resolver::proto::op::{Message, MessageType, ResponseCode};
+use hickory_resolver::proto::rr::{DNSClass, RecordType};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+use crate::dns_utils;
+
+const UPSTREAM_TIMEOUT: Duration = Duration::from_millis(1500);
+const MAX_CACHE_TTL: u32 = 3600;
+const NEGATIVE_CACHE_TTL: u32 = 60;
+const MAX_PACKET: usize = 4096;
+/// How long an idle client TCP connection is kept open
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Settings for the local forwarding proxy.
+#[derive(Debug, Clone)]
+pub struct ProxyConfig {
+    pub listen: SocketAddr,
+    /// Candidate resolvers; re-ranked by latency in the background
+    pub upstreams: Vec<SocketAddr>,
+    pub rebenchmark_interval: Duration,
+    pub cache_size: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    name: String,
+    record_type: RecordType,
+    class: DNSClass,
+}
+
+struct CacheEntry {
+    response: Message,
+    stored: Instant,
+    ttl: u32,
+}
+
+/// A small TTL-respecting response cache.
+struct Cache {
+    entries: HashMap<CacheKey, CacheEntry>,
+    capacity: usize,
+}
+
+impl Cache {
+    fn new(capacity: usize) -> Self {
+        Self { entries: HashMap::new(), capacity }
+    }
+
+    /// The cached answer for `key`, with the remaining TTL written into its records.
+    fn get(&mut self, key: &CacheKey) -> Option<Message> {
+        let entry = self.entries.get(key)?;
+        let age = entry.stored.elapsed().as_secs() as u32;
+        if age >= entry.ttl {
+            self.entries.remove(key);
+            return None;
+        }
+
+        let mut response = entry.response.clone();
+        let remaining = entry.ttl - age;
+        for record in response.answers_mut() {
+            record.set_ttl(record.ttl().min(remaining));
+        }
+        for record in response.name_servers_mut() {
+            record.set_ttl(record.ttl().min(remaining));
+        }
+        Some(response)
+    }
+
+    fn insert(&mut self, key: CacheKey, response: Message) {
+        let Some(ttl) = cacheable_ttl(&response) else {
+            return;
+        };
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() >= self.capacity {
+            self.entries.retain(|_, e| e.stored.elapsed().as_secs() < e.ttl as u64);
+        }
+        if self.entries.len() >= self.capacity {
+            // Still full: drop whatever expires soonest
+            let soonest = self.entries.iter()
+                .min_by_key(|(_, e)| e.ttl.saturating_sub(e.stored.elapsed().as_secs() as u32))
+                .map(|(k, _)| k.clone());
+            if let Some(k) = soonest {
+                self.entries.remove(&k);
+            }
+        }
+        self.entries.insert(key, CacheEntry { response, stored: Instant::now(), ttl });
+    }
+}
+
+/// How long a response may be cached: the smallest answer TTL, or the SOA
+/// TTL for negative answers. `None` for errors and truncated responses.
+fn cacheable_ttl(response: &Message) -> Option<u32> {
+    if response.truncated() {
+        return None;
+    }
+    let ttl = match response.response_code() {
+        ResponseCode::NoError if !response.answers().is_empty() => {
+            response.answers().iter().map(|r| r.ttl()).min()?
+        }
+        ResponseCode::NoError | ResponseCode::NXDomain => response.name_servers().iter()
+            .find(|r| r.record_type() == RecordType::SOA)
+            .map(|r| r.ttl())
+            .unwrap_or(NEGATIVE_CACHE_TTL),
+        _ => return None,
+    };
+    (ttl > 0).then(|| ttl.min(MAX_CACHE_TTL))
+}
+
+/// Order `upstreams` by measured latency, fastest first; unreachable ones go last.
+pub async fn rank_upstreams(upstreams: &[SocketAddr]) -> Vec<SocketAddr> {
+    let mut measured = Vec::with_capacity(upstreams.len());
+    for addr in upstreams {
+        let latency = dns_utils::test_latency_at(*addr).await.ok();
+        measured.push((*addr, latency));
+    }
+    measured.sort_by_key(|(_, latency)| latency.unwrap_or(Duration::MAX));
+    measured.into_iter().map(|(addr, _)| addr).collect()
+}
+
+/// Send `query` to each upstream in turn until one answers.
+async fn forward(query: &[u8], id: u16, upstreams: &[SocketAddr]) -> Result<Vec<u8>> {
+    let mut last_error = anyhow::anyhow!("No upstream resolvers configured");
+    for upstream in upstreams {
+        match forward_to(query, id, *upstream).await {
+            Ok(response) => return Ok(response),
+            Err(e) => {
+                eprintln!("Upstream {} failed: {}. Failing over...", upstream, e);
+                last_error = e;
+            }
+        }
+    }
+    Err(last_error)
+}
+
+async fn forward_to(query: &[u8], id: u16, upstream: SocketAddr) -> Result<Vec<u8>> {
+    let bind: SocketAddr = match upstream.ip() {
+        IpAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        IpAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(upstream).await?;
+    socket.send(query).await?;
+
+    let mut buf = vec![0u8; MAX_PACKET];
+    let response = tokio::time::timeout(UPSTREAM_TIMEOUT, async {
+        loop {
+            let len = socket.recv(&mut buf).await?;
+            // Ignore stray datagrams that don't answer our query
+            if len >= 2 && u16::from_be_bytes([buf[0], buf[1]]) == id {
+                return Ok::<_, anyhow::Error>(buf[..len].to_vec());
+            }
+        }
+    })
+    .await
+    .context("Timed out")??;
+
+    // TC=1: the answer did not fit in a datagram, ask again over TCP
+    if response.len() > 2 && response[2] & 0x02 != 0 {
+        return forward_tcp(query, id, upstream).await;
+    }
+    Ok(response)
+}
+
+async fn forward_tcp(query: &[u8], id: u16, upstream: SocketAddr) -> Result<Vec<u8>> {
+    tokio::time::timeout(UPSTREAM_TIMEOUT, async {
+        let mut stream = TcpStream::connect(upstream).await?;
+        write_tcp_message(&mut stream, query).await?;
+        let response = read_tcp_message(&mut stream).await?.context("Connection closed")?;
+        if response.len() < 2 || u16::from_be_bytes([response[0], response[1]]) != id {
+            anyhow::bail!("TCP answer does not match the query");
+        }
+        Ok(response)
+    })
+    .await
+    .context("Timed out over TCP")?
+}
+
+/// Read one length-prefixed DNS message; `None` when the peer closed the connection.
+async fn read_tcp_message(stream: &mut TcpStream) -> Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 2];
+    match stream.read_exact(&mut len).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let mut message = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut message).await?;
+    Ok(Some(message))
+}
+
+async fn write_tcp_message(stream: &mut TcpStream, message: &[u8]) -> Result<()> {
+    let len = u16::try_from(message.len()).context("DNS message too large for TCP")?;
+    let mut framed = Vec::with_capacity(message.len() + 2);
+    framed.extend_from_slice(&len.to_be_bytes());
+    framed.extend_from_slice(message);
+    stream.write_all(&framed).await?;
+    Ok(())
+}
+
+/// Shrink `response` to what a UDP client accepts (512 bytes, or its EDNS size):
+/// an oversized answer becomes an empty one with TC=1, so the client retries over TCP.
+fn fit_udp(query: &[u8], response: Vec<u8>) -> Vec<u8> {
+    let limit = Message::from_vec(query).map(|q| q.max_payload() as usize).unwrap_or(512);
+    if response.len() <= limit {
+        return response;
+    }
+    let Ok(mut message) = Message::from_vec(&response) else {
+        return response;
+    };
+    message.take_answers();
+    message.take_name_servers();
+    message.take_additionals();
+    message.set_truncated(true);
+    message.to_vec().unwrap_or(response)
+}
+
+/// Serve one client TCP connection: length-prefixed queries, answered in order.
+async fn serve_tcp_client(
+    mut stream: TcpStream,
+    ranking: Arc<RwLock<Vec<SocketAddr>>>,
+    cache: Arc<Mutex<Cache>>,
+) {
+    while let Ok(Ok(Some(packet))) = tokio::time::timeout(TCP_IDLE_TIMEOUT, read_tcp_message(&mut stream)).await {
+        let Some(response) = handle_query(packet, Arc::clone(&ranking), Arc::clone(&cache)).await else {
+            return;
+        };
+        if write_tcp_message(&mut stream, &response).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Handle one client datagram: answer from cache, or forward and cache the reply.
+async fn handle_query(
+    packet: Vec<u8>,
+    ranking: Arc<RwLock<Vec<SocketAddr>>>,
+    cache: Arc<Mutex<Cache>>,
+) -> Option<Vec<u8>> {
+    let request = Message::from_vec(&packet).ok()?;
+    if request.message_type() != MessageType::Query {
+        return None;
+    }
+    let key = request.queries().first().map(|q| CacheKey {
+        name: q.name().to_ascii().to_lowercase(),
+        record_type: q.query_type(),
+        class: q.query_class(),
+    });
+
+    if let Some(key) = &key
+        && let Some(mut cached) = cache.lock().unwrap().get(key)
+    {
+        cached.set_id(request.id());
+        return cached.to_vec().ok();
+    }
+
+    let upstreams = ranking.read().unwrap().clone();
+    let response = match forward(&packet, request.id(), &upstreams).await {
+        Ok(response) => response,
+        Err(e) => {
+            eprintln!("All upstreams failed: {}", e);
+            let mut failure = Message::error_msg(request.id(), request.op_code(), ResponseCode::ServFail);
+            failure.add_queries(request.queries().to_vec());
+            return failure.to_vec().ok();
+        }
+    };
+
+    if let Some(key) = key
+        && let Ok(message) = Message::from_vec(&response)
+    {
+        cache.lock().unwrap().insert(key, message);
+    }
+    Some(response)
+}
+
+/// Run the forwarding proxy until the process is stopped.
+///
+/// Queries arrive over UDP or TCP on the same address and go to the fastest
+/// upstream, failing over to the next on timeouts. Truncated upstream answers
+/// are fetched again over TCP. The upstreams are re-ranked every
+/// `rebenchmark_interval` in the background.
+pub async fn serve(config: ProxyConfig) -> Result<()> {
+    if config.upstreams.is_empty() {
+        anyhow::bail!("No upstream DNS servers given. Use -d, --csv or --json.");
+    }
+
+    let socket = Arc::new(
+        UdpSocket::bind(config.listen)
+            .await
+            .with_context(|| format!("Failed to listen on {}", config.listen))?,
+    );
+    let listener = TcpListener::bind(config.listen)
+        .await
+        .with_context(|| format!("Failed to listen on {} (TCP)", config.listen))?;
+    eprintln!("Ranking {} upstream resolvers...", config.upstreams.len());
+    let ranking = Arc::new(RwLock::new(rank_upstreams(&config.upstreams).await));
+    let cache = Arc::new(Mutex::new(Cache::new(config.cache_size)));
+    eprintln!("Listening on {} (fastest upstream: {})", config.listen, ranking.read().unwrap()[0]);
+
+    // Background re-benchmark
+    let bench_ranking = Arc::clone(&ranking);
+    let upstreams = config.upstreams.clone();
+    let interval = config.rebenchmark_interval;
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            let ranked = rank_upstreams(&upstreams).await;
+            let mut current = bench_ranking.write().unwrap();
+            if current.first() != ranked.first() {
+                eprintln!("Fastest upstream is now {}", ranked[0]);
+            }
+            *current = ranked;
+        }
+    });
+
+    let tcp_ranking = Arc::clone(&ranking);
+    let tcp_cache = Arc::clone(&cache);
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(serve_tcp_client(stream, Arc::clone(&tcp_ranking), Arc::clone(&tcp_cache)));
+                }
+                Err(e) => eprintln!("TCP accept failed: {}", e),
+            }
+        }
+    });
+
+    let mut buf = vec![0u8; MAX_PACKET];
+    loop {
+        let (len, client) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                eprintln!("Receive failed: {}", e);
+                continue;
+            }
+        };
+        let packet = buf[..len].to_vec();
+        let socket = Arc::clone(&socket);
+        let ranking = Arc::clone(&ranking);
+        let cache = Arc::clone(&cache);
+        tokio::spawn(async move {
+            if let Some(response) = handle_query(packet.clone(), ranking, cache).await {
+                let _ = socket.send_to(&fit_udp(&packet, response), client).await;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_resolver::proto::op::Query;
+    use hickory_resolver::proto::rr::rdata::A;
+    use hickory_resolver::proto::rr::{Name, RData, Record};
+    use std::net::Ipv4Addr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const ANSWER: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+
+    /// Answer `query` with ANSWER for A questions and an empty NOERROR otherwise.
+    fn stub_answer(query: &[u8], ttl: u32, truncated: bool) -> Vec<u8> {
+        let query = Message::from_vec(query).unwrap();
+        let mut response = Message::new();
+        response.set_id(query.id()).set_message_type(MessageType::Response).set_recursion_available(true);
+        response.add_queries(query.queries().to_vec());
+        if truncated {
+            response.set_truncated(true);
+        } else if let Some(q) = query.queries().first()
+            && q.query_type() == RecordType::A
+        {
+            response.add_answer(Record::from_rdata(q.name().clone(), ttl, RData::A(A(ANSWER))));
+        }
+        response.to_vec().unwrap()
+    }
+
+    /// A local upstream that answers over UDP (and, when `udp_truncates`, only with
+    /// TC=1 there and fully over TCP). Returns its address and a count of UDP queries.
+    async fn stub_upstream(ttl: u32, udp_truncates: bool) -> (SocketAddr, Arc<AtomicUsize>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let queries = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&queries);
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; MAX_PACKET];
+            while let Ok((len, client)) = socket.recv_from(&mut buf).await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let _ = socket.send_to(&stub_answer(&buf[..len], ttl, udp_truncates), client).await;
+            }
+        });
+
+        if udp_truncates {
+            let listener = TcpListener::bind(addr).await.unwrap();
+            tokio::spawn(async move {
+                while let Ok((mut stream, _)) = listener.accept().await {
+                    while let Ok(Some(query)) = read_tcp_message(&mut stream).await {
+                        let _ = write_tcp_message(&mut stream, &stub_answer(&query, ttl, false)).await;
+                    }
+                }
+            });
+        }
+        (addr, queries)
+    }
+
+    fn a_query(id: u16, name: &str) -> Vec<u8> {
+        let mut query = Message::new();
+        query.set_id(id).set_recursion_desired(true);
+        query.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+        query.to_vec().unwrap()
+    }
+
+    fn answers(response: &[u8]) -> Vec<(IpAddr, u32)> {
+        Message::from_vec(response).unwrap().answers().iter()
+            .filter_map(|r| Some((r.data().ip_addr()?, r.ttl())))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn ranks_answering_upstream_first_and_fails_over() {
+        // Bound but never read: queries to it time out
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let silent_addr = silent.local_addr().unwrap();
+        let (answering, queries) = stub_upstream(300, false).await;
+
+        let ranked = rank_upstreams(&[silent_addr, answering]).await;
+        assert_eq!(ranked, vec![answering, silent_addr]);
+
+        // With a stale ranking the silent upstream is tried first, then skipped
+        let ranking = Arc::new(RwLock::new(vec![silent_addr, answering]));
+        let cache = Arc::new(Mutex::new(Cache::new(16)));
+        let before = queries.load(Ordering::SeqCst);
+        let response = handle_query(a_query(0x1234, "example.com."), ranking, cache).await.unwrap();
+
+        assert_eq!(u16::from_be_bytes([response[0], response[1]]), 0x1234);
+        assert_eq!(answers(&response), vec![(IpAddr::V4(ANSWER), 300)]);
+        assert_eq!(queries.load(Ordering::SeqCst), before + 1);
+    }
+
+    #[tokio::test]
+    async fn caches_answers_until_their_ttl_expires() {
+        let (upstream, queries) = stub_upstream(1, false).await;
+        let ranking = Arc::new(RwLock::new(vec![upstream]));
+        let cache = Arc::new(Mutex::new(Cache::new(16)));
+
+        let first = handle_query(a_query(1, "example.com."), Arc::clone(&ranking), Arc::clone(&cache)).await.unwrap();
+        let second = handle_query(a_query(2, "EXAMPLE.com."), Arc::clone(&ranking), Arc::clone(&cache)).await.unwrap();
+        assert_eq!(queries.load(Ordering::SeqCst), 1, "second query should come from the cache");
+        assert_eq!(u16::from_be_bytes([second[0], second[1]]), 2);
+        assert_eq!(answers(&first), answers(&second));
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        handle_query(a_query(3, "example.com."), ranking, cache).await.unwrap();
+        assert_eq!(queries.load(Ordering::SeqCst), 2, "expired answer should be fetched again");
+    }
+
+    #[tokio::test]
+    async fn retries_truncated_answers_over_tcp() {
+        let (upstream, _) = stub_upstream(300, true).await;
+
+        let response = forward(&a_query(7, "example.com."), 7, &[upstream]).await.unwrap();
+
+        assert!(!Message::from_vec(&response).unwrap().truncated());
+        assert_eq!(answers(&response), vec![(IpAddr::V4(ANSWER), 300)]);
+    }
+
+    #[test]
+    fn oversized_udp_answers_are_truncated() {
+        let query = a_query(9, "example.com.");
+        let mut response = Message::from_vec(&stub_answer(&query, 300, false)).unwrap();
+        let name = Name::from_ascii("example.com.").unwrap();
+        for i in 0..60 {
+            response.add_answer(Record::from_rdata(name.clone(), 300, RData::A(A(Ipv4Addr::new(192, 0, 2, i)))));
+        }
+        let response = response.to_vec().unwrap();
+        assert!(response.len() > 512);
+
+        let fitted = Message::from_vec(&fit_udp(&query, response)).unwrap();
+        assert!(fitted.truncated());
+        assert!(fitted.answers().is_empty());
+        assert_eq!(fitted.id(), 9);
+    }
+}
@@ -4,18 +4,29 @@ use hickory_resolver::name_server::TokioConnectionProvider;
 use hickory_resolver::proto::xfer::Protocol;
 use hickory_resolver::Resolver;
 use reqwest::Client;
+use serde::{Deserialize, Serialize};
 use std::net::{IpAddr, SocketAddr};
 use std::time::{Duration, Instant};
 
 const TEST_DOMAIN: &str = "www.google.com";
 const DOWNLOAD_TEST_URL: &str = "https://speed.cloudflare.com/__down?bytes=1000000"; // 1MB file
 const DOWNLOAD_TIMEOUT_SECS: u64 = 7; // Slightly less than 7.5 to be safe
+// "whoami" names: their authoritative servers answer with the address of the resolver asking
+const WHOAMI_A_DOMAIN: &str = "whoami.akamai.net.";
+const WHOAMI_TXT_DOMAIN: &str = "o-o.myaddr.l.google.com.";
+const BENCHMARK_FILE: &str = "last_benchmark.json";
+const DOT_PORT: u16 = 853;
+const DOT_TIMEOUT: Duration = Duration::from_secs(3);
 
 type TokioResolver = Resolver<TokioConnectionProvider>;
 
 /// Create a DNS resolver configured to use a specific DNS server.
 fn create_resolver(dns_ip: IpAddr) -> TokioResolver {
-    let socket_addr = SocketAddr::new(dns_ip, 53);
+    create_resolver_at(SocketAddr::new(dns_ip, 53))
+}
+
+/// Create a DNS resolver for a server listening on any address and port.
+fn create_resolver_at(socket_addr: SocketAddr) -> TokioResolver {
     let name_server = NameServerConfig::new(socket_addr, Protocol::Udp);
     let mut config = ResolverConfig::new();
     config.add_name_server(name_server);
@@ -31,15 +42,70 @@ fn create_resolver(dns_ip: IpAddr) -> TokioResolver {
 
 /// Measure the latency (resolution time) for a given DNS server.
 pub async fn test_latency(dns_ip: IpAddr) -> Result<Duration> {
-    let resolver = create_resolver(dns_ip);
+    test_latency_at(SocketAddr::new(dns_ip, 53)).await
+}
+
+/// Measure the latency for a DNS server on any address and port.
+pub async fn test_latency_at(server: SocketAddr) -> Result<Duration> {
+    let resolver = create_resolver_at(server);
     let start = Instant::now();
     resolver
         .lookup_ip(TEST_DOMAIN)
         .await
-        .with_context(|| format!("Failed to resolve {} via {}", TEST_DOMAIN, dns_ip))?;
+        .with_context(|| format!("Failed to resolve {} via {}", TEST_DOMAIN, server))?;
     Ok(start.elapsed())
 }
 
+/// Check that `dns_ip` answers DNS-over-TLS on port 853 with a certificate valid
+/// for `tls_name`, by resolving the test domain over it.
+pub async fn test_dot(dns_ip: IpAddr, tls_name: &str) -> Result<Duration> {
+    use hickory_resolver::proto::op::{Message, MessageType, Query};
+    use hickory_resolver::proto::rr::{Name, RecordType};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::rustls::pki_types::ServerName;
+
+    let connector = crate::http_timing::tls_connector()?;
+    let server_name = ServerName::try_from(tls_name.to_string()).context("Invalid TLS server name")?;
+    let mut query = Message::new();
+    query
+        .set_id(std::process::id() as u16)
+        .set_recursion_desired(true)
+        .add_query(Query::query(Name::from_ascii(TEST_DOMAIN)?, RecordType::A));
+    let query = query.to_vec()?;
+
+    let start = Instant::now();
+    let response = tokio::time::timeout(DOT_TIMEOUT, async {
+        let tcp = tokio::net::TcpStream::connect((dns_ip, DOT_PORT)).await?;
+        let mut tls = connector.connect(server_name, tcp).await?;
+        tls.write_all(&(query.len() as u16).to_be_bytes()).await?;
+        tls.write_all(&query).await?;
+        tls.flush().await?;
+        let mut len = [0u8; 2];
+        tls.read_exact(&mut len).await?;
+        let mut response = vec![0u8; u16::from_be_bytes(len) as usize];
+        tls.read_exact(&mut response).await?;
+        Ok::<_, std::io::Error>(response)
+    })
+    .await
+    .with_context(|| format!("DNS-over-TLS to {} timed out", dns_ip))?
+    .with_context(|| format!("DNS-over-TLS to {} as {} failed", dns_ip, tls_name))?;
+
+    let response = Message::from_vec(&response).context("Invalid DNS-over-TLS response")?;
+    if response.message_type() != MessageType::Response || response.id() != std::process::id() as u16 {
+        anyhow::bail!("Unexpected DNS-over-TLS response from {}", dns_ip);
+    }
+    Ok(start.elapsed())
+}
+
+/// The servers among `servers` that advertise DNS-over-TLS and pass `test_dot`.
+pub async fn working_dot(servers: &[DnsServer]) -> Vec<IpAddr> {
+    let probes = servers.iter().filter_map(|s| {
+        let name = s.dot_name()?.to_string();
+        Some(async move { test_dot(s.ip, &name).await.ok().map(|_| s.ip) })
+    });
+    futures_util::future::join_all(probes).await.into_iter().flatten().collect()
+}
+
 /// Measure download speed (in Mbps) by resolving a URL through a specific DNS and downloading.
 ///
 /// This test checks how well the DNS routes us to a fast CDN edge.
@@ -87,7 +153,61 @@ pub async fn test_download_speed(dns_ip: IpAddr) -> Result<f64> {
     }
 }
 
+/// A DNS server with its catalog details. Only `ip` is required; servers typed
+/// into the TUI or given with `--dns` have nothing else.
 #[derive(Debug, Clone)]
+pub struct DnsServer {
+    pub ip: IpAddr,
+    pub name: Option<String>,
+    pub provider: Option<String>,
+    /// e.g. `anti-sanction`, `ad-block`, `family`, `public`
+    pub tags: Vec<String>,
+    /// Transports the server speaks, e.g. `udp;dot;doh` (the benchmark uses plain DNS)
+    pub protocol: Option<String>,
+    /// Name on the server's TLS certificate, for DNS-over-TLS (e.g. `dns.google`)
+    pub tls_name: Option<String>,
+    pub notes: Option<String>,
+}
+
+impl DnsServer {
+    pub fn new(ip: IpAddr) -> Self {
+        Self { ip, name: None, provider: None, tags: Vec::new(), protocol: None, tls_name: None, notes: None }
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t.eq_ignore_ascii_case(tag))
+    }
+
+    /// The TLS name to use for DNS-over-TLS, if the server speaks it and we know the name
+    pub fn dot_name(&self) -> Option<&str> {
+        let speaks_dot = self.protocol.as_deref()
+            .is_some_and(|p| p.split(';').any(|t| t.trim().eq_ignore_ascii_case("dot")));
+        self.tls_name.as_deref().filter(|_| speaks_dot)
+    }
+
+    /// Name and provider for display, e.g. `OpenDNS (Cisco)`
+    pub fn label(&self) -> Option<String> {
+        match (&self.name, &self.provider) {
+            (Some(name), Some(provider)) if name != provider => Some(format!("{} ({})", name, provider)),
+            (Some(name), _) => Some(name.clone()),
+            (None, provider) => provider.clone(),
+        }
+    }
+
+    /// Fill in details from a later catalog layer, keeping ours where it has none
+    pub fn merge(&mut self, other: DnsServer) {
+        self.name = other.name.or(self.name.take());
+        self.provider = other.provider.or(self.provider.take());
+        if !other.tags.is_empty() {
+            self.tags = other.tags;
+        }
+        self.protocol = other.protocol.or(self.protocol.take());
+        self.tls_name = other.tls_name.or(self.tls_name.take());
+        self.notes = other.notes.or(self.notes.take());
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DnsTestResult {
     pub ip: IpAddr,
     pub latency: Option<Duration>,
@@ -106,6 +226,90 @@ impl DnsTestResult {
     }
 }
 
+/// Order results best-first: working servers by download speed, then latency;
+/// failed ones last.
+pub fn rank_results(results: &mut [DnsTestResult]) {
+    use std::cmp::Ordering;
+
+    let usable = |r: &DnsTestResult| r.error.is_none() && (r.latency.is_some() || r.download_speed_mbps.is_some());
+    results.sort_by(|a, b| {
+        usable(b).cmp(&usable(a))
+            .then_with(|| match (a.download_speed_mbps, b.download_speed_mbps) {
+                (Some(s1), Some(s2)) => s2.partial_cmp(&s1).unwrap_or(Ordering::Equal),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            })
+            .then_with(|| match (a.latency, b.latency) {
+                (Some(l1), Some(l2)) => l1.cmp(&l2),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            })
+    });
+}
+
+/// The servers from `results` that passed, best first.
+pub fn ranked_servers(results: &[DnsTestResult]) -> Vec<IpAddr> {
+    let mut ranked = results.to_vec();
+    rank_results(&mut ranked);
+    ranked
+        .into_iter()
+        .filter(|r| r.error.is_none() && (r.latency.is_some() || r.download_speed_mbps.is_some()))
+        .map(|r| r.ip)
+        .collect()
+}
+
+/// The last DNS benchmark, kept on disk for the watchdog.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BenchmarkState {
+    pub results: Vec<DnsTestResult>,
+    /// Resolver last applied to the system by this tool
+    pub applied: Option<IpAddr>,
+}
+
+impl BenchmarkState {
+    /// Load the saved benchmark, or an empty one if there is none.
+    pub fn load() -> Self {
+        crate::file_loader::config_dir()
+            .and_then(|dir| std::fs::read_to_string(dir.join(BENCHMARK_FILE)).ok())
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let dir = crate::file_loader::config_dir().context("Could not determine the config directory")?;
+        std::fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        let path = dir.join(BENCHMARK_FILE);
+        std::fs::write(&path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Replace the stored results, keeping the applied resolver.
+    pub fn record_results(results: &[DnsTestResult]) -> Result<()> {
+        let mut state = Self::load();
+        state.results = results.to_vec();
+        state.save()
+    }
+
+    /// Remember which resolver is now applied.
+    pub fn record_applied(ip: IpAddr) -> Result<()> {
+        let mut state = Self::load();
+        state.applied = Some(ip);
+        state.save()
+    }
+}
+
+/// Test every server in turn without the TUI, reporting progress on stderr.
+pub async fn benchmark_all(servers: &[IpAddr]) -> Vec<DnsTestResult> {
+    let mut results = Vec::with_capacity(servers.len());
+    for (i, ip) in servers.iter().enumerate() {
+        eprintln!("[{}/{}] Testing {}...", i + 1, servers.len(), ip);
+        results.push(run_full_test(*ip).await);
+    }
+    results
+}
+
 /// Run a full test (latency + download speed) for a given DNS server.
 /// Enforces a hard 7.5-second limit for the entire process.
 pub async fn run_full_test(dns_ip: IpAddr) -> DnsTestResult {
@@ -140,3 +344,72 @@ pub async fn run_full_test(dns_ip: IpAddr) -> DnsTestResult {
         }
     }
 }
+
+/// Result of checking which resolver the system's queries actually leave through.
+#[derive(Debug, Clone)]
+pub struct ResolverCheck {
+    /// Egress addresses seen when querying through the system resolver
+    pub via_system: Vec<IpAddr>,
+    /// Egress addresses seen when querying the expected resolver directly
+    pub via_expected: Vec<IpAddr>,
+    pub passed: bool,
+}
+
+/// Ask the "whoami" names which address our query came from, through `resolver`.
+async fn egress_addresses(resolver: &TokioResolver) -> Result<Vec<IpAddr>> {
+    let mut addrs = Vec::new();
+
+    if let Ok(lookup) = resolver.lookup_ip(WHOAMI_A_DOMAIN).await {
+        addrs.extend(lookup.iter());
+    }
+    if let Ok(lookup) = resolver.txt_lookup(WHOAMI_TXT_DOMAIN).await {
+        // Google answers with the resolver IP, plus an "edns0-client-subnet ..." string we skip
+        addrs.extend(lookup.iter().filter_map(|txt| txt.to_string().trim().parse::<IpAddr>().ok()));
+    }
+
+    addrs.sort();
+    addrs.dedup();
+    if addrs.is_empty() {
+        anyhow::bail!("Neither {} nor {} could be resolved", WHOAMI_A_DOMAIN, WHOAMI_TXT_DOMAIN);
+    }
+    Ok(addrs)
+}
+
+/// Whether two egress addresses belong to the same resolver site.
+/// Big resolvers egress from a pool, so compare /24 (IPv4) and /48 (IPv6) prefixes.
+fn same_egress_network(a: &IpAddr, b: &IpAddr) -> bool {
+    match (a, b) {
+        (IpAddr::V4(a), IpAddr::V4(b)) => a.octets()[..3] == b.octets()[..3],
+        (IpAddr::V6(a), IpAddr::V6(b)) => a.segments()[..3] == b.segments()[..3],
+        _ => false,
+    }
+}
+
+/// Check that queries made through the system resolver really reach `expected`.
+///
+/// Resolves the "whoami" names once through the system stub and once directly
+/// through `expected`, and passes if both egress from the same network. A VPN,
+/// a browser-style DoH override or a stale stub shows up as a mismatch.
+pub async fn verify_system_resolver(expected: IpAddr) -> Result<ResolverCheck> {
+    let mut opts = ResolverOpts::default();
+    opts.timeout = Duration::from_secs(2);
+    opts.attempts = 1;
+    opts.cache_size = 0;
+    let system = Resolver::builder_tokio()
+        .context("Failed to read the system resolver configuration")?
+        .with_options(opts)
+        .build();
+
+    let via_system = egress_addresses(&system)
+        .await
+        .context("Lookup through the system resolver failed")?;
+    let via_expected = egress_addresses(&create_resolver(expected))
+        .await
+        .with_context(|| format!("Lookup through {} failed", expected))?;
+
+    let passed = via_system
+        .iter()
+        .any(|s| via_expected.iter().any(|e| same_egress_network(s, e)));
+
+    Ok(ResolverCheck { via_system, via_expected, passed })
+}
@@ -2,86 +2,906 @@ use anyhow::{Context, Result};
 use std::fs::File;
 use std::io::BufReader;
 use std::net::IpAddr;
-use serde::Deserialize;
-use crate::mirror_utils::{Distro, Mirror};
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::dns_utils::DnsServer;
+use crate::mirror_apply::{
+    apt_source_files, deb822_field, deb822_stanzas, is_deb822, parse_deb822_stanza, parse_legacy_line, AptArchive,
+    AptEntry, DOCKER_DAEMON_JSON, PACMAN_MIRRORLIST,
+};
+use crate::mirror_utils::{expand_probe, mirror_base_url, DetectedSystem, Distro, Mirror, MirrorOrigin};
 
+const USER_MIRRORS_FILE: &str = "mirrors.csv";
+// Catalogs compiled into the binary, so it works outside the repository
+const BUILTIN_MIRRORS: &str = include_str!("../examples/mirrors.csv");
+const BUILTIN_DNS: &str = include_str!("../examples/dns.csv");
+// pacman only falls through to later servers when earlier ones fail
+const MAX_CURRENT_PACMAN_SERVERS: usize = 3;
+
+/// A DNS catalog row. Only `ip` is required. In CSV, `tags` are separated by `;`;
+/// JSON may also give them as an array.
 #[derive(Deserialize)]
 struct DnsRecord {
     ip: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    provider: Option<String>,
+    #[serde(default)]
+    tags: Option<Tags>,
+    #[serde(default)]
+    protocol: Option<String>,
+    #[serde(default)]
+    tls_name: Option<String>,
+    #[serde(default)]
+    notes: Option<String>,
 }
 
 #[derive(Deserialize)]
-struct MirrorRecord {
-    name: String,
-    url: String,
-    distro: String,
+#[serde(untagged)]
+enum Tags {
+    List(Vec<String>),
+    Joined(String),
 }
 
-pub fn load_mirrors(csv_path: &str, current_distro: Distro) -> Result<Vec<Mirror>> {
-    let file = File::open(csv_path).with_context(|| format!("Failed to open Mirror CSV: {}", csv_path))?;
-    let mut reader = csv::Reader::from_reader(file);
-    
-    let mut mirrors = Vec::new();
-    for result in reader.deserialize() {
-        let record: MirrorRecord = result.with_context(|| format!("Failed to parse Mirror record in: {}", csv_path))?;
-        let distro = match record.distro.to_lowercase().as_str() {
-            "arch" => Distro::Arch,
-            "debian" => Distro::Debian,
-            "ubuntu" => Distro::Ubuntu,
-            "kali" => Distro::Kali,
-            "mint" => Distro::Mint,
-            "manjaro" => Distro::Manjaro,
-            "docker" => Distro::Docker,
-            "androidsdk" => Distro::AndroidSDK,
-            _ => Distro::Unknown,
+impl DnsRecord {
+    fn into_server(self, source: &str) -> Result<DnsServer> {
+        let ip = self.ip.trim().parse::<IpAddr>()
+            .with_context(|| format!("Invalid IP address in {}: {}", source, self.ip))?;
+        let tags = match self.tags {
+            Some(Tags::List(tags)) => tags,
+            Some(Tags::Joined(tags)) => tags.split(';').map(str::to_string).collect(),
+            None => Vec::new(),
         };
+        let text = |s: Option<String>| s.map(|s| s.trim().to_string()).filter(|s| !s.is_empty());
+        Ok(DnsServer {
+            ip,
+            name: text(self.name),
+            provider: text(self.provider),
+            tags: tags.into_iter().map(|t| t.trim().to_lowercase()).filter(|t| !t.is_empty()).collect(),
+            protocol: text(self.protocol),
+            tls_name: text(self.tls_name),
+            notes: text(self.notes),
+        })
+    }
+}
 
-        // Include mirror if it's for the current distro OR a global service
-        if distro == current_distro || 
-           distro == Distro::Docker || 
-           distro == Distro::AndroidSDK {
-            mirrors.push(Mirror {
+/// A mirror list row: `base_url` plus an optional `probe` path template, or
+/// (older lists) a full test file `url`. In the user's mirror file, a row
+/// replaces the listed mirror of the same name, and `removed` drops it.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MirrorRecord {
+    pub name: String,
+    #[serde(default)]
+    pub base_url: Option<String>,
+    #[serde(default)]
+    pub probe: Option<String>,
+    #[serde(default)]
+    pub url: Option<String>,
+    pub distro: String,
+    #[serde(default)]
+    pub removed: bool,
+}
+
+/// The user's own mirror additions, edits and removals, made in the TUI.
+pub fn user_mirrors_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join(USER_MIRRORS_FILE))
+}
+
+/// One layer of the mirror or DNS catalog.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CatalogSource {
+    /// The list compiled into the binary
+    Builtin,
+    File(PathBuf),
+}
+
+impl CatalogSource {
+    /// Short name for the info panel, with the home directory as `~`
+    pub fn label(&self) -> String {
+        match self {
+            CatalogSource::Builtin => "built-in".to_string(),
+            CatalogSource::File(path) => {
+                let home = std::env::var_os("HOME").map(PathBuf::from);
+                match home.as_deref().and_then(|home| path.strip_prefix(home).ok()) {
+                    Some(rest) => format!("~/{}", rest.display()),
+                    None => path.display().to_string(),
+                }
+            }
+        }
+    }
+}
+
+/// `<prefix>*.csv` and `<prefix>*.json` in the config directory, sorted by name.
+fn config_catalogs(prefix: &str) -> Vec<PathBuf> {
+    let Some(entries) = config_dir().and_then(|dir| std::fs::read_dir(dir).ok()) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| {
+            let name = p.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            name.starts_with(prefix) && matches!(p.extension().and_then(|e| e.to_str()), Some("csv") | Some("json"))
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// Mirror catalog layers, lowest first: the built-in list, then
+/// `mirrors*.csv|json` from the config directory, then `explicit` (`--mirrors`).
+/// The user's TUI edits file goes on top of these when loading.
+pub fn mirror_sources(explicit: Option<&Path>) -> Vec<CatalogSource> {
+    let user = user_mirrors_path();
+    let mut sources = vec![CatalogSource::Builtin];
+    sources.extend(
+        config_catalogs("mirrors")
+            .into_iter()
+            .filter(|p| Some(p) != user.as_ref())
+            .map(CatalogSource::File),
+    );
+    sources.extend(explicit.map(|p| CatalogSource::File(p.to_path_buf())));
+    sources
+}
+
+/// DNS catalog layers: the built-in list, then `dns*.csv|json` from the config directory.
+pub fn dns_sources() -> Vec<CatalogSource> {
+    let mut sources = vec![CatalogSource::Builtin];
+    sources.extend(config_catalogs("dns").into_iter().map(CatalogSource::File));
+    sources
+}
+
+/// Every DNS server in `sources`, in order. A server listed again in a later
+/// layer keeps its place and takes that layer's details.
+pub fn load_dns_sources(sources: &[CatalogSource]) -> Result<Vec<DnsServer>> {
+    let mut servers: Vec<DnsServer> = Vec::new();
+    for source in sources {
+        let found = match source {
+            CatalogSource::Builtin => dns_from_csv(BUILTIN_DNS.as_bytes(), "built-in list")?,
+            CatalogSource::File(path) if path.extension().and_then(|e| e.to_str()) == Some("json") => {
+                load_json(&path.to_string_lossy())?
+            }
+            CatalogSource::File(path) => load_csv(&path.to_string_lossy())?,
+        };
+        for server in found {
+            match servers.iter_mut().find(|s| s.ip == server.ip) {
+                Some(existing) => existing.merge(server),
+                None => servers.push(server),
+            }
+        }
+    }
+    Ok(servers)
+}
+
+/// The user's configuration directory: `$XDG_CONFIG_HOME` (or `~/.config`),
+/// `%APPDATA%` on Windows.
+pub fn user_config_base() -> Option<PathBuf> {
+    if cfg!(target_os = "windows") {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    }
+}
+
+/// Directory for the user's DNS Master settings:
+/// `$XDG_CONFIG_HOME/dns-master` (or `~/.config/dns-master`), `%APPDATA%\dns-master` on Windows.
+pub fn config_dir() -> Option<PathBuf> {
+    user_config_base().map(|dir| dir.join("dns-master"))
+}
+
+/// Load the mirrors for `current_distro` and the global services from the
+/// catalog layers in `sources`, each overlaying the ones before it.
+pub fn load_mirrors(sources: &[CatalogSource], current_distro: Distro, system: &DetectedSystem) -> Result<Vec<Mirror>> {
+    let records = load_mirror_records(sources)?;
+
+    let mut mirrors = Vec::new();
+    for record in records {
+        let distro = Distro::from_name(&record.distro);
+        let mirror = match (record.base_url, record.url) {
+            (Some(base), _) => mirror_at(record.name, &base, record.probe.as_deref(), distro, system),
+            (None, Some(url)) => Mirror {
                 name: record.name,
-                url: record.url,
+                base_url: mirror_base_url(&url),
+                url,
                 distro,
-            });
+                origin: MirrorOrigin::Catalog,
+            },
+            (None, None) => {
+                return Err(anyhow::anyhow!("Mirror '{}' has neither base_url nor url", record.name));
+            }
+        };
+
+        // Include mirror if it's for the current distro OR a global service
+        if mirror.distro == current_distro || mirror.distro.is_global_service() {
+            mirrors.push(mirror);
         }
     }
     
     Ok(mirrors)
 }
 
-/// Load DNS addresses from a JSON file.
-/// Expected format: [{"ip": "8.8.8.8"}, {"ip": "1.1.1.1"}]
-pub fn load_json(path: &str) -> Result<Vec<IpAddr>> {
+/// Merge catalog layers: each one's rows replace or remove same-named rows of the ones before.
+pub fn load_mirror_records(sources: &[CatalogSource]) -> Result<Vec<MirrorRecord>> {
+    let mut records = Vec::new();
+    for source in sources {
+        overlay_mirror_records(&mut records, read_mirror_records(source)?);
+    }
+    Ok(records)
+}
+
+pub fn read_mirror_records(source: &CatalogSource) -> Result<Vec<MirrorRecord>> {
+    let path = match source {
+        CatalogSource::Builtin => return mirror_records_from_csv(BUILTIN_MIRRORS.as_bytes(), "built-in list"),
+        CatalogSource::File(path) => path,
+    };
+    let file = File::open(path).with_context(|| format!("Failed to open mirror list: {}", path.display()))?;
+    if path.extension().and_then(|e| e.to_str()) == Some("json") {
+        serde_json::from_reader(BufReader::new(file)).with_context(|| format!("Failed to parse mirror list: {}", path.display()))
+    } else {
+        mirror_records_from_csv(file, &path.display().to_string())
+    }
+}
+
+fn mirror_records_from_csv(reader: impl std::io::Read, label: &str) -> Result<Vec<MirrorRecord>> {
+    csv::Reader::from_reader(reader)
+        .deserialize()
+        .map(|result| result.with_context(|| format!("Failed to parse Mirror record in: {}", label)))
+        .collect()
+}
+
+pub fn write_mirror_records(path: &Path, records: &[MirrorRecord]) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for record in records {
+        writer.serialize(record)?;
+    }
+    let content = String::from_utf8(writer.into_inner()?)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    crate::fs_utils::write_atomic(path, &content)
+}
+
+/// Apply `overrides` on top of `records`: each row replaces the mirror of the
+/// same name, or removes it when marked `removed`.
+pub fn overlay_mirror_records(records: &mut Vec<MirrorRecord>, overrides: Vec<MirrorRecord>) {
+    for record in overrides {
+        records.retain(|r| r.name != record.name);
+        if !record.removed {
+            records.push(record);
+        }
+    }
+}
+
+fn read_user_records(user_path: &Path) -> Result<Vec<MirrorRecord>> {
+    if user_path.exists() {
+        read_mirror_records(&CatalogSource::File(user_path.to_path_buf()))
+    } else {
+        Ok(Vec::new())
+    }
+}
+
+/// Save `record` to the user's mirror file, replacing the entry named
+/// `replaces` (the mirror being edited). The edited mirror's `probe` and `url`
+/// carry over unless `record` sets its own; a legacy `url` also supplies the
+/// probe, since `base_url` takes precedence over it. A renamed mirror from the
+/// lower `catalog` layers gets a removal row so it doesn't come back under its old name.
+pub fn save_user_mirror(user_path: &Path, catalog: &[CatalogSource], replaces: Option<&str>, mut record: MirrorRecord) -> Result<()> {
+    let mut records = read_user_records(user_path)?;
+    if let Some(old) = replaces {
+        let original = match records.iter().find(|r| r.name == old && !r.removed) {
+            Some(r) => Some(r.clone()),
+            None => load_mirror_records(catalog)?.into_iter().find(|r| r.name == old),
+        };
+        if let Some(original) = original {
+            let legacy_probe = original.url.as_deref().filter(|_| original.base_url.is_none()).map(|url| {
+                url[mirror_base_url(url).len()..].to_string()
+            });
+            record.probe = record.probe.or(original.probe).or(legacy_probe);
+            record.url = record.url.or(original.url);
+        }
+    }
+    records.retain(|r| r.name != record.name && Some(r.name.as_str()) != replaces);
+    if let Some(old) = replaces.filter(|old| *old != record.name)
+        && in_catalog(catalog, old)?
+    {
+        records.push(removal(old));
+    }
+    records.push(record);
+    write_mirror_records(user_path, &records)
+}
+
+/// Drop mirror `name` from the user's list, recording a removal when it comes
+/// from the lower `catalog` layers.
+pub fn remove_user_mirror(user_path: &Path, catalog: &[CatalogSource], name: &str) -> Result<()> {
+    let mut records = read_user_records(user_path)?;
+    records.retain(|r| r.name != name);
+    if in_catalog(catalog, name)? {
+        records.push(removal(name));
+    }
+    write_mirror_records(user_path, &records)
+}
+
+fn in_catalog(catalog: &[CatalogSource], name: &str) -> Result<bool> {
+    Ok(load_mirror_records(catalog)?.iter().any(|r| r.name == name))
+}
+
+fn removal(name: &str) -> MirrorRecord {
+    MirrorRecord { name: name.to_string(), base_url: None, probe: None, url: None, distro: String::new(), removed: true }
+}
+
+/// A mirror rooted at `base`, probed with `probe` (or the distro's default probe).
+fn mirror_at(name: String, base: &str, probe: Option<&str>, distro: Distro, system: &DetectedSystem) -> Mirror {
+    let base_url = base.trim_end_matches('/').to_string();
+    let probe = probe.or(distro.default_probe()).unwrap_or("");
+    let url = format!("{}/{}", base_url, expand_probe(probe, &distro, system).trim_start_matches('/'));
+    Mirror { name, url, base_url, distro, origin: MirrorOrigin::Catalog }
+}
+
+fn host_name(url: &str) -> String {
+    reqwest::Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_string))
+        .unwrap_or_else(|| url.to_string())
+}
+
+/// Mirrors read from an upstream list, with a warning when part of the request
+/// could not be honoured.
+#[derive(Debug)]
+pub struct MirrorImport {
+    pub mirrors: Vec<Mirror>,
+    pub warning: Option<String>,
+}
+
+/// Import mirrors from an official upstream list, detecting the format from
+/// its content: Arch `mirrorlist` or mirror status JSON, Ubuntu `mirrors.txt`,
+/// Debian `Mirrors.masterlist` or a Fedora metalink. `country` keeps only
+/// mirrors in that country: an ISO code (`IR`) or, where the list has one, a
+/// name (`Iran`). Arch mirrorlists only carry names; Ubuntu's `mirrors.txt`
+/// has no country at all, so the filter is skipped with a warning.
+pub fn import_mirrors(path: &str, country: Option<&str>, system: &DetectedSystem) -> Result<MirrorImport> {
+    let content = std::fs::read_to_string(path).with_context(|| format!("Failed to open mirror list: {}", path))?;
+    parse_mirror_import(&content, country, system).with_context(|| format!("Failed to import {}", path))
+}
+
+fn parse_mirror_import(content: &str, country: Option<&str>, system: &DetectedSystem) -> Result<MirrorImport> {
+    let trimmed = content.trim_start();
+
+    let mut warning = None;
+    let mut no_match_hint = "";
+    let (distro, bases) = if trimmed.starts_with('{') {
+        (Distro::Arch, parse_arch_status(content, country).context("Failed to parse Arch mirror status")?)
+    } else if trimmed.starts_with("<?xml") || trimmed.starts_with("<metalink") {
+        (Distro::Fedora, parse_fedora_metalink(content, country))
+    } else if content.lines().any(|l| l.starts_with("Site:")) {
+        (Distro::Debian, parse_debian_masterlist(content, country))
+    } else if content.lines().any(|l| l.trim_start_matches('#').trim_start().starts_with("Server")) {
+        no_match_hint = " (mirrorlists only name countries, e.g. `Iran`)";
+        (Distro::Arch, parse_arch_mirrorlist(content, country))
+    } else {
+        // mirrors.txt is already per country (mirrors.ubuntu.com/<CC>.txt) and says not which
+        if let Some(country) = country {
+            warning = Some(format!(
+                "Ubuntu mirrors.txt has no country information; --country {} is ignored (download mirrors.ubuntu.com/<CC>.txt instead)",
+                country
+            ));
+        }
+        let urls = content.lines().map(str::trim).filter(|l| l.starts_with("http")).map(str::to_string).collect();
+        (Distro::Ubuntu, urls)
+    };
+    if let Some(country) = country
+        && bases.is_empty()
+    {
+        anyhow::bail!("No {} mirrors matched country {}{}", distro.as_str(), country, no_match_hint);
+    }
+
+    let mut mirrors = Vec::new();
+    for base in bases {
+        if mirrors.iter().any(|m: &Mirror| m.base_url == base.trim_end_matches('/')) {
+            continue;
+        }
+        let name = format!("{} - {}", host_name(&base), distro.as_str());
+        mirrors.push(Mirror { origin: MirrorOrigin::Imported, ..mirror_at(name, &base, None, distro.clone(), system) });
+    }
+    Ok(MirrorImport { mirrors, warning })
+}
+
+fn read_optional(path: &Path) -> Result<String> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(content),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+/// The mirrors a system is configured with, and the config files that could
+/// not be read. One unreadable file does not hide the others.
+#[derive(Debug)]
+pub struct CurrentMirrors {
+    pub mirrors: Vec<Mirror>,
+    pub unreadable: Vec<String>,
+}
+
+/// The mirrors the system under `root` uses right now for `distro`, plus its
+/// Docker registry mirrors, so they can be benchmarked as a baseline. Missing
+/// config files just contribute nothing. `catalog` holds the listed mirrors,
+/// whose hosts count as `distro` archive hosts like applying does.
+pub fn load_current_mirrors(root: &Path, distro: &Distro, catalog: &[Mirror], system: &DetectedSystem) -> CurrentMirrors {
+    let mut unreadable = Vec::new();
+    let mut report = |result: Result<Vec<String>>| {
+        result.unwrap_or_else(|e| {
+            unreadable.push(format!("{:#}", e));
+            Vec::new()
+        })
+    };
+
+    let bases = match distro {
+        Distro::Arch | Distro::Manjaro => report(read_optional(&root.join(PACMAN_MIRRORLIST)).map(|content| {
+            let mut bases = parse_pacman_servers(&content, distro);
+            bases.truncate(MAX_CURRENT_PACMAN_SERVERS);
+            bases
+        })),
+        Distro::Debian | Distro::Ubuntu | Distro::Kali | Distro::Mint => {
+            let known = catalog.iter().filter(|m| &m.distro == distro).map(|m| m.base_url.as_str());
+            report(current_apt_bases(root, &AptArchive::new(apt_archive_of(distro).to_string(), known)))
+        }
+        _ => Vec::new(),
+    };
+    let registries = report(current_registry_mirrors(root));
+
+    let mut mirrors: Vec<Mirror> = Vec::new();
+    let all = bases.into_iter().map(|b| (b, distro.clone())).chain(registries.into_iter().map(|b| (b, Distro::Docker)));
+    for (base, distro) in all {
+        let name = format!("{} - {}", host_name(&base), distro.as_str());
+        let mirror = Mirror { origin: MirrorOrigin::Current, ..mirror_at(name, &base, None, distro, system) };
+        if !mirrors.iter().any(|m| m.same_root(&mirror)) {
+            mirrors.push(mirror);
+        }
+    }
+    CurrentMirrors { mirrors, unreadable }
+}
+
+/// `registry-mirrors` of Docker's `daemon.json`.
+fn current_registry_mirrors(root: &Path) -> Result<Vec<String>> {
+    let daemon_json = read_optional(&root.join(DOCKER_DAEMON_JSON))?;
+    if daemon_json.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    let config: serde_json::Value = serde_json::from_str(&daemon_json)
+        .with_context(|| format!("Invalid JSON in /{}", DOCKER_DAEMON_JSON))?;
+    Ok(config["registry-mirrors"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|v| v.as_str().map(str::to_string))
+        .collect())
+}
+
+/// Repository roots of the active `Server =` lines of a pacman mirrorlist,
+/// in order. Manjaro servers also carry the branch (`.../manjaro/stable/$repo/$arch`).
+fn parse_pacman_servers(content: &str, distro: &Distro) -> Vec<String> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.trim().split_once('=')?;
+            if key.trim() != "Server" {
+                return None;
+            }
+            let url = value.trim();
+            let mut base = url.split("/$").next().unwrap_or(url).trim_end_matches('/');
+            if *distro == Distro::Manjaro {
+                for branch in ["/stable", "/testing", "/unstable"] {
+                    base = base.strip_suffix(branch).unwrap_or(base);
+                }
+            }
+            Some(base.to_string())
+        })
+        .collect()
+}
+
+/// Name of `distro`'s APT archive, the last path segment of its mirrors' URIs.
+fn apt_archive_of(distro: &Distro) -> &'static str {
+    match distro {
+        Distro::Mint => "linuxmint",
+        Distro::Kali => "kali",
+        Distro::Ubuntu => "ubuntu",
+        _ => "debian",
+    }
+}
+
+/// URIs of the enabled APT sources (one-line and deb822) that point at
+/// `archive`: exactly the ones applying a mirror would rewrite.
+fn current_apt_bases(root: &Path, archive: &AptArchive) -> Result<Vec<String>> {
+    let mut bases = Vec::new();
+    for path in apt_source_files(root) {
+        let content = read_optional(&path)?;
+        let lines: Vec<&str> = content.lines().collect();
+        let entries: Vec<AptEntry> = if is_deb822(&path) {
+            deb822_stanzas(&lines).into_iter().map(|stanza| parse_deb822_stanza(&lines[stanza])).collect()
+        } else {
+            lines.iter().filter_map(|line| parse_legacy_line(line)).map(|(entry, _)| entry).collect()
+        };
+        for entry in entries.iter().filter(|e| e.enabled) {
+            bases.extend(entry.archive_uris(archive).into_iter().map(str::to_string));
+        }
+    }
+    Ok(bases)
+}
+
+fn country_matches(filter: Option<&str>, code: &str, name: &str) -> bool {
+    filter.is_none_or(|f| f.eq_ignore_ascii_case(code) || f.eq_ignore_ascii_case(name))
+}
+
+/// `Server = https://host/archlinux/$repo/os/$arch` lines (commented or not)
+/// grouped under `## Country` headers, which carry only the country's name.
+fn parse_arch_mirrorlist(content: &str, country: Option<&str>) -> Vec<String> {
+    let mut current_country = String::new();
+    let mut bases = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if let Some(header) = line.strip_prefix("##") {
+            current_country = header.trim().to_string();
+            continue;
+        }
+        let Some((key, value)) = line.trim_start_matches('#').split_once('=') else {
+            continue;
+        };
+        if key.trim() == "Server" && country_matches(country, "", &current_country) {
+            let url = value.trim();
+            bases.push(url.split("/$repo").next().unwrap_or(url).to_string());
+        }
+    }
+    bases
+}
+
+#[derive(Deserialize)]
+struct ArchStatus {
+    urls: Vec<ArchStatusUrl>,
+}
+
+#[derive(Deserialize)]
+struct ArchStatusUrl {
+    url: String,
+    protocol: String,
+    #[serde(default)]
+    country: String,
+    #[serde(default)]
+    country_code: String,
+    #[serde(default)]
+    active: Option<bool>,
+}
+
+/// archlinux.org/mirrors/status/json/: active HTTP(S) mirrors.
+fn parse_arch_status(content: &str, country: Option<&str>) -> Result<Vec<String>> {
+    let status: ArchStatus = serde_json::from_str(content)?;
+    Ok(status
+        .urls
+        .into_iter()
+        .filter(|u| matches!(u.protocol.as_str(), "http" | "https") && u.active != Some(false))
+        .filter(|u| country_matches(country, &u.country_code, &u.country))
+        .map(|u| u.url)
+        .collect())
+}
+
+/// RFC 822 stanzas with `Site:`, `Archive-http:` and `Country: IR Iran`.
+fn parse_debian_masterlist(content: &str, country: Option<&str>) -> Vec<String> {
+    let lines: Vec<&str> = content.lines().collect();
+    let mut bases = Vec::new();
+    for stanza in deb822_stanzas(&lines) {
+        let stanza = &lines[stanza];
+        let field = |name: &str| stanza.iter().find_map(|l| deb822_field(l, name)).map(|(_, value)| value.trim());
+        let (Some(site), Some(path)) = (field("Site"), field("Archive-http")) else {
+            continue;
+        };
+        let (code, name) = field("Country").and_then(|c| c.split_once(' ')).unwrap_or(("", ""));
+        if country_matches(country, code, name.trim()) {
+            bases.push(format!("http://{}{}", site, path));
+        }
+    }
+    bases
+}
+
+/// `<url protocol="https" location="IR">.../fedora/linux/releases/43/.../repomd.xml</url>`
+fn parse_fedora_metalink(content: &str, country: Option<&str>) -> Vec<String> {
+    let attr = |tag: &str, name: &str| {
+        let start = tag.find(&format!("{}=\"", name))? + name.len() + 2;
+        tag[start..].split('"').next().map(str::to_string)
+    };
+
+    let mut bases = Vec::new();
+    for part in content.split("<url ").skip(1) {
+        let Some((tag, rest)) = part.split_once('>') else {
+            continue;
+        };
+        let url = rest.split('<').next().unwrap_or("").trim();
+        let location = attr(tag, "location").unwrap_or_default();
+        if !url.starts_with("http") || !country_matches(country, &location, &location) {
+            continue;
+        }
+        // The repo root is above releases/ or updates/
+        let base = ["/releases/", "/updates/", "/development/"]
+            .iter()
+            .find_map(|m| url.find(m).map(|i| &url[..i]))
+            .map_or_else(|| mirror_base_url(url), str::to_string);
+        bases.push(base);
+    }
+    bases
+}
+
+/// Load DNS servers from a JSON file.
+/// Expected format: [{"ip": "1.1.1.1", "name": "Cloudflare", "tags": ["public"]}, {"ip": "8.8.8.8"}]
+pub fn load_json(path: &str) -> Result<Vec<DnsServer>> {
     let file = File::open(path).with_context(|| format!("Failed to open JSON file: {}", path))?;
     let reader = BufReader::new(file);
     let records: Vec<DnsRecord> = serde_json::from_reader(reader)
         .with_context(|| format!("Failed to parse JSON file: {}", path))?;
 
-    let mut ips = Vec::new();
-    for record in records {
-        let ip = record.ip.parse::<IpAddr>()
-            .with_context(|| format!("Invalid IP address in JSON: {}", record.ip))?;
-        ips.push(ip);
-    }
-    Ok(ips)
+    records.into_iter().map(|record| record.into_server("JSON")).collect()
 }
 
-/// Load DNS addresses from a CSV file.
-/// Expected format: ip
+/// Load DNS servers from a CSV file.
+/// Expected format: ip,name,provider,tags,protocol,tls_name,notes
+///                 1.1.1.1,Cloudflare,Cloudflare,public,udp;dot;doh,cloudflare-dns.com,
 ///                 8.8.8.8
-///                 1.1.1.1
-pub fn load_csv(path: &str) -> Result<Vec<IpAddr>> {
+/// Only the `ip` column is required.
+pub fn load_csv(path: &str) -> Result<Vec<DnsServer>> {
     let file = File::open(path).with_context(|| format!("Failed to open CSV file: {}", path))?;
-    let mut reader = csv::Reader::from_reader(file);
-    
-    let mut ips = Vec::new();
+    dns_from_csv(file, path)
+}
+
+fn dns_from_csv(reader: impl std::io::Read, label: &str) -> Result<Vec<DnsServer>> {
+    let mut reader = csv::Reader::from_reader(reader);
+
+    let mut servers = Vec::new();
     for result in reader.deserialize() {
-        let record: DnsRecord = result.with_context(|| format!("Failed to parse CSV record in: {}", path))?;
-        let ip = record.ip.parse::<IpAddr>()
-            .with_context(|| format!("Invalid IP address in CSV: {}", record.ip))?;
-        ips.push(ip);
+        let record: DnsRecord = result.with_context(|| format!("Failed to parse CSV record in: {}", label))?;
+        servers.push(record.into_server("CSV")?);
+    }
+    Ok(servers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs_utils::TempDir;
+    use crate::mirror_apply::APT_SOURCES_LIST;
+    use crate::mirror_utils::parse_os_release;
+
+    const ARCH_MIRRORLIST: &str = include_str!("../examples/imports/arch-mirrorlist");
+    const ARCH_STATUS: &str = include_str!("../examples/imports/arch-status.json");
+    const UBUNTU_MIRRORS: &str = include_str!("../examples/imports/ubuntu-mirrors-IR.txt");
+    const DEBIAN_MASTERLIST: &str = include_str!("../examples/imports/Mirrors.masterlist");
+    const FEDORA_METALINK: &str = include_str!("../examples/imports/fedora-metalink.xml");
+
+    #[test]
+    fn arch_mirrorlist_matches_country_by_name() {
+        let iran = ["https://mirror.arvancloud.ir/archlinux", "https://repo.iut.ac.ir/repo/archlinux"];
+        assert_eq!(parse_arch_mirrorlist(ARCH_MIRRORLIST, Some("Iran")), iran);
+        assert_eq!(parse_arch_mirrorlist(ARCH_MIRRORLIST, Some("iran")), iran);
+        assert_eq!(parse_arch_mirrorlist(ARCH_MIRRORLIST, Some("Germany")).len(), 2);
+        assert!(parse_arch_mirrorlist(ARCH_MIRRORLIST, Some("IR")).is_empty());
+        assert_eq!(parse_arch_mirrorlist(ARCH_MIRRORLIST, None).len(), 4);
+    }
+
+    #[test]
+    fn arch_status_keeps_active_http_mirrors() {
+        assert_eq!(
+            parse_arch_status(ARCH_STATUS, Some("IR")).unwrap(),
+            ["https://mirror.arvancloud.ir/archlinux/", "https://repo.iut.ac.ir/repo/archlinux/"]
+        );
+        assert_eq!(parse_arch_status(ARCH_STATUS, Some("Germany")).unwrap(), ["https://mirror.netcologne.de/archlinux/"]);
+    }
+
+    #[test]
+    fn debian_masterlist_needs_an_http_archive() {
+        assert_eq!(parse_debian_masterlist(DEBIAN_MASTERLIST, Some("IR")), ["http://mirror.arvancloud.ir/debian/"]);
+        assert_eq!(
+            parse_debian_masterlist(DEBIAN_MASTERLIST, None),
+            ["http://mirror.arvancloud.ir/debian/", "http://ftp.de.debian.org/debian/"]
+        );
+    }
+
+    #[test]
+    fn fedora_metalink_cuts_urls_at_the_repo_root() {
+        assert_eq!(parse_fedora_metalink(FEDORA_METALINK, Some("IR")), ["https://mirror.arvancloud.ir/fedora/linux"]);
+        assert_eq!(
+            parse_fedora_metalink(FEDORA_METALINK, Some("DE")),
+            ["https://ftp.fau.de/fedora/linux", "http://mirror.netcologne.de/fedora/linux"]
+        );
+    }
+
+    #[test]
+    fn import_detects_the_format() {
+        let dir = TempDir::new("import");
+        let system = parse_os_release("");
+        let cases = [
+            ("mirrorlist", ARCH_MIRRORLIST, "Iran", Distro::Arch, "https://mirror.arvancloud.ir/archlinux"),
+            ("status.json", ARCH_STATUS, "IR", Distro::Arch, "https://mirror.arvancloud.ir/archlinux"),
+            ("IR.txt", UBUNTU_MIRRORS, "IR", Distro::Ubuntu, "https://mirror.arvancloud.ir/ubuntu"),
+            ("Mirrors.masterlist", DEBIAN_MASTERLIST, "IR", Distro::Debian, "http://mirror.arvancloud.ir/debian"),
+            ("metalink.xml", FEDORA_METALINK, "IR", Distro::Fedora, "https://mirror.arvancloud.ir/fedora/linux"),
+        ];
+        for (file, content, country, distro, first) in cases {
+            let path = dir.write(file, content);
+            let import = import_mirrors(path.to_str().unwrap(), Some(country), &system).unwrap();
+            assert!(import.mirrors.iter().all(|m| m.distro == distro && m.origin == MirrorOrigin::Imported), "{}", file);
+            assert_eq!(import.mirrors[0].base_url, first, "{}", file);
+        }
+    }
+
+    #[test]
+    fn import_warns_when_the_list_has_no_countries() {
+        let dir = TempDir::new("import-ubuntu");
+        let path = dir.write("IR.txt", UBUNTU_MIRRORS);
+        let system = parse_os_release("");
+        let import = import_mirrors(path.to_str().unwrap(), Some("DE"), &system).unwrap();
+        assert!(!import.mirrors.is_empty());
+        assert!(import.warning.unwrap().contains("--country DE is ignored"));
+        assert!(import_mirrors(path.to_str().unwrap(), None, &system).unwrap().warning.is_none());
+    }
+
+    #[test]
+    fn import_reports_an_unmatched_country() {
+        let dir = TempDir::new("import-country");
+        let path = dir.write("mirrorlist", ARCH_MIRRORLIST);
+        let err = import_mirrors(path.to_str().unwrap(), Some("IR"), &parse_os_release("")).unwrap_err();
+        let err = format!("{:#}", err);
+        assert!(err.contains("No Arch mirrors matched country IR (mirrorlists only name countries"), "{}", err);
+    }
+
+    fn bases(current: &CurrentMirrors) -> Vec<(&str, &Distro)> {
+        current.mirrors.iter().map(|m| (m.base_url.as_str(), &m.distro)).collect()
+    }
+
+    #[test]
+    fn current_pacman_mirrors_are_the_first_active_servers() {
+        let root = TempDir::new("current-pacman");
+        root.write(
+            PACMAN_MIRRORLIST,
+            "## Iran\n#Server = https://commented.example/archlinux/$repo/os/$arch\nServer = https://mirror.arvancloud.ir/archlinux/$repo/os/$arch\n\
+             Server=https://repo.iut.ac.ir/repo/archlinux/$repo/os/$arch\nServer = https://a.example/archlinux/$repo/os/$arch\n\
+             Server = https://b.example/archlinux/$repo/os/$arch\n",
+        );
+        let current = load_current_mirrors(root.path(), &Distro::Arch, &[], &parse_os_release(""));
+        assert_eq!(
+            bases(&current),
+            [
+                ("https://mirror.arvancloud.ir/archlinux", &Distro::Arch),
+                ("https://repo.iut.ac.ir/repo/archlinux", &Distro::Arch),
+                ("https://a.example/archlinux", &Distro::Arch),
+            ]
+        );
+        assert!(current.mirrors.iter().all(|m| m.origin == MirrorOrigin::Current));
+        assert_eq!(
+            parse_pacman_servers("Server = https://mirror.example/manjaro/stable/$repo/$arch", &Distro::Manjaro),
+            ["https://mirror.example/manjaro"]
+        );
+    }
+
+    #[test]
+    fn current_apt_mirrors_are_the_uris_apply_rewrites() {
+        let root = TempDir::new("current-apt");
+        let system = parse_os_release("");
+        root.write(
+            APT_SOURCES_LIST,
+            "# deb http://old.archive.ubuntu.com/ubuntu jammy main\n\
+             deb [arch=amd64] http://ir.archive.ubuntu.com/ubuntu jammy main restricted\n\
+             deb-src http://ir.archive.ubuntu.com/ubuntu jammy main\n\
+             deb\thttp://mirror.example.ir/ubuntu jammy-updates main\n\
+             deb http://security.ubuntu.com/ubuntu jammy-security main\n\
+             deb https://packagecloud.io/org/repo/ubuntu/ jammy main\n\
+             deb http://unknown.example/ubuntu jammy main\n",
+        );
+        root.write(
+            "etc/apt/sources.list.d/ubuntu.sources",
+            "types: deb\nuris: http://de.archive.ubuntu.com/ubuntu\nsuites: noble-security noble\ncomponents: main\n \n\
+             Types: deb\nURIs: http://fr.archive.ubuntu.com/ubuntu\nSuites: noble\nComponents: main\nEnabled: no\n",
+        );
+        root.write("etc/apt/sources.list.d/docker.list", "deb https://download.docker.com/linux/ubuntu jammy stable\n");
+        root.write("etc/apt/sources.list.d/notes.txt", "deb http://nl.archive.ubuntu.com/ubuntu jammy main\n");
+        let catalog = [mirror_at("Example".into(), "http://mirror.example.ir/ubuntu", None, Distro::Ubuntu, &system)];
+
+        let current = load_current_mirrors(root.path(), &Distro::Ubuntu, &catalog, &system);
+
+        assert_eq!(
+            bases(&current),
+            [
+                ("http://ir.archive.ubuntu.com/ubuntu", &Distro::Ubuntu),
+                ("http://mirror.example.ir/ubuntu", &Distro::Ubuntu),
+                ("http://de.archive.ubuntu.com/ubuntu", &Distro::Ubuntu),
+            ]
+        );
+        assert!(current.unreadable.is_empty());
+    }
+
+    #[test]
+    fn current_registry_mirrors_come_from_daemon_json() {
+        let root = TempDir::new("current-docker");
+        root.write(DOCKER_DAEMON_JSON, r#"{"registry-mirrors": ["https://docker.arvancloud.ir", 42], "debug": true}"#);
+        let current = load_current_mirrors(root.path(), &Distro::Fedora, &[], &parse_os_release(""));
+        assert_eq!(bases(&current), [("https://docker.arvancloud.ir", &Distro::Docker)]);
+    }
+
+    #[test]
+    fn invalid_daemon_json_is_reported_without_hiding_the_rest() {
+        let root = TempDir::new("current-invalid");
+        root.write(PACMAN_MIRRORLIST, "Server = https://mirror.arvancloud.ir/archlinux/$repo/os/$arch\n");
+        root.write(DOCKER_DAEMON_JSON, "{ not json");
+        let current = load_current_mirrors(root.path(), &Distro::Arch, &[], &parse_os_release(""));
+        assert_eq!(bases(&current), [("https://mirror.arvancloud.ir/archlinux", &Distro::Arch)]);
+        assert_eq!(current.unreadable.len(), 1);
+        assert!(current.unreadable[0].contains("Invalid JSON"), "{:?}", current.unreadable);
+    }
+
+    fn record(name: &str, base_url: &str, distro: &str) -> MirrorRecord {
+        MirrorRecord {
+            name: name.to_string(),
+            base_url: Some(base_url.to_string()),
+            probe: None,
+            url: None,
+            distro: distro.to_string(),
+            removed: false,
+        }
+    }
+
+    fn names(records: &[MirrorRecord]) -> Vec<(&str, bool)> {
+        records.iter().map(|r| (r.name.as_str(), r.removed)).collect()
+    }
+
+    #[test]
+    fn overlay_replaces_by_name_and_drops_removed_rows() {
+        let mut records = vec![record("A", "https://a.example", "Arch"), record("B", "https://b.example", "Arch")];
+        overlay_mirror_records(
+            &mut records,
+            vec![record("A", "https://a2.example", "Arch"), removal("B"), removal("missing"), record("C", "https://c.example", "Docker")],
+        );
+        assert_eq!(names(&records), [("A", false), ("C", false)]);
+        assert_eq!(records[0].base_url.as_deref(), Some("https://a2.example"));
+    }
+
+    #[test]
+    fn user_mirror_edits_layer_over_the_catalog() {
+        let dir = TempDir::new("user-mirrors");
+        let catalog = vec![CatalogSource::File(dir.write(
+            "catalog.csv",
+            "name,base_url,probe,url,distro\n\
+             Listed,https://listed.example/archlinux,custom/probe.db,,Arch\n\
+             Legacy,,,https://legacy.example/ubuntu/ls-lR.gz,Ubuntu\n",
+        ))];
+        let user = dir.path().join("user/mirrors.csv");
+        let layered = |user: &Path| {
+            let mut sources = catalog.clone();
+            sources.push(CatalogSource::File(user.to_path_buf()));
+            load_mirror_records(&sources).unwrap()
+        };
+
+        // Editing a listed mirror in place replaces it by name and keeps its probe
+        save_user_mirror(&user, &catalog, Some("Listed"), record("Listed", "https://new.example/archlinux", "Arch")).unwrap();
+        let records = read_user_records(&user).unwrap();
+        assert_eq!(names(&records), [("Listed", false)]);
+        assert_eq!(records[0].probe.as_deref(), Some("custom/probe.db"));
+        assert_eq!(layered(&user).iter().filter(|r| r.name == "Listed").count(), 1);
+
+        // Renaming a legacy url-only mirror hides the old name and keeps its test file
+        save_user_mirror(&user, &catalog, Some("Legacy"), record("Renamed", "https://legacy.example/ubuntu", "Ubuntu")).unwrap();
+        let records = read_user_records(&user).unwrap();
+        assert_eq!(names(&records), [("Listed", false), ("Legacy", true), ("Renamed", false)]);
+        assert_eq!(records[2].probe.as_deref(), Some("/ls-lR.gz"));
+        assert_eq!(records[2].url.as_deref(), Some("https://legacy.example/ubuntu/ls-lR.gz"));
+        let mirrors = load_mirrors(&[catalog[0].clone(), CatalogSource::File(user.clone())], Distro::Ubuntu, &parse_os_release("")).unwrap();
+        assert_eq!(mirrors.iter().map(|m| m.url.as_str()).collect::<Vec<_>>(), ["https://legacy.example/ubuntu/ls-lR.gz"]);
+
+        // Removing a listed mirror records a removal; a user-only mirror just goes away
+        remove_user_mirror(&user, &catalog, "Listed").unwrap();
+        remove_user_mirror(&user, &catalog, "Renamed").unwrap();
+        assert_eq!(names(&read_user_records(&user).unwrap()), [("Legacy", true), ("Listed", true)]);
+        assert!(layered(&user).is_empty());
+
+        // Re-adding a removed mirror replaces its removal row
+        save_user_mirror(&user, &catalog, None, record("Listed", "https://listed.example/archlinux", "Arch")).unwrap();
+        assert_eq!(names(&read_user_records(&user).unwrap()), [("Legacy", true), ("Listed", false)]);
+        assert_eq!(names(&layered(&user)), [("Listed", false)]);
     }
-    Ok(ips)
 }
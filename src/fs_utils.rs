@@ -0,0 +1,66 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Replace `path` with `content` without readers ever seeing a partial file:
+/// write a temporary file next to it, then rename it into place.
+pub fn write_atomic(path: &Path, content: &str) -> Result<()> {
+    let file_name = path.file_name().context("Path has no file name")?.to_string_lossy();
+    let tmp = path.with_file_name(format!(".{}.dns-master.tmp", file_name));
+
+    fs::write(&tmp, content)
+        .with_context(|| format!("Failed to write {} (are you root?)", tmp.display()))?;
+    // Keep the original's permissions (e.g. a 0644 system file)
+    if let Ok(meta) = fs::metadata(path) {
+        let _ = fs::set_permissions(&tmp, meta.permissions());
+    }
+    fs::rename(&tmp, path)
+        .with_context(|| format!("Failed to move {} into place", tmp.display()))
+}
+
+/// Copy `path` to `<path>.bak.<unix time>` and return the backup's path.
+pub fn backup_timestamped(path: &Path) -> Result<PathBuf> {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let file_name = path.file_name().context("Path has no file name")?.to_string_lossy();
+    let backup = path.with_file_name(format!("{}.bak.{}", file_name, secs));
+    fs::copy(path, &backup)
+        .with_context(|| format!("Failed to back up {} (are you root?)", path.display()))?;
+    Ok(backup)
+}
+
+/// A fresh, empty scratch directory for tests, removed when dropped.
+#[cfg(test)]
+pub struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub fn new(label: &str) -> Self {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let dir = std::env::temp_dir().join(format!("dns-master-{}-{}-{}", label, std::process::id(), n));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("create temp dir");
+        Self(dir)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Write `content` to `rel` under the directory, creating parent directories.
+    pub fn write(&self, rel: &str, content: &str) -> PathBuf {
+        let path = self.0.join(rel);
+        fs::create_dir_all(path.parent().unwrap()).expect("create parent dir");
+        fs::write(&path, content).expect("write fixture");
+        path
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
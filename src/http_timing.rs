@@ -0,0 +1,342 @@
+use anyhow::{anyhow, Context, Result};
+use reqwest::Url;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::Instant as Deadline;
+use tokio_rustls::rustls::{pki_types::ServerName, ClientConfig};
+use tokio_rustls::TlsConnector;
+
+const MAX_REDIRECTS: usize = 5;
+const MAX_HEADER_BYTES: usize = 64 * 1024;
+
+/// Where the time of one HTTP GET went.
+#[derive(Debug, Clone)]
+pub struct HttpTimings {
+    pub dns: Duration,
+    pub connect: Duration,
+    /// `None` for plain HTTP
+    pub tls: Option<Duration>,
+    /// From sending the request to the first response byte
+    pub ttfb: Duration,
+    /// Body throughput after the first byte
+    pub transfer_mbps: f64,
+    /// Body bytes actually read (payload only for chunked responses)
+    pub bytes: usize,
+    /// The server answered the Range request with the whole file
+    pub range_ignored: bool,
+}
+
+/// The byte range pulled from every mirror's test file, so each mirror
+/// transfers the same amount of data.
+#[derive(Debug, Clone, Copy)]
+pub struct ByteWindow {
+    pub offset: u64,
+    pub len: usize,
+}
+
+/// The server answered 416: the file ends before the requested range starts.
+#[derive(Debug)]
+pub struct RangeNotSatisfiable {
+    pub offset: u64,
+}
+
+impl std::fmt::Display for RangeNotSatisfiable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Test file is smaller than the {} byte offset", self.offset)
+    }
+}
+
+impl std::error::Error for RangeNotSatisfiable {}
+
+trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+pub(crate) fn tls_connector() -> Result<TlsConnector> {
+    static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+    if let Some(config) = CONFIG.get() {
+        return Ok(TlsConnector::from(config.clone()));
+    }
+    use rustls_platform_verifier::ConfigVerifierExt;
+    let config = Arc::new(ClientConfig::with_platform_verifier().context("Failed to load TLS roots")?);
+    Ok(TlsConnector::from(CONFIG.get_or_init(|| config).clone()))
+}
+
+/// Run one phase of the request, failing if it is still going at `deadline`.
+async fn phase<T>(what: &str, deadline: Deadline, fut: impl Future<Output = std::io::Result<T>>) -> Result<T> {
+    tokio::time::timeout_at(deadline, fut)
+        .await
+        .map_err(|_| anyhow!("{} timed out", what))?
+        .with_context(|| format!("{} failed", what))
+}
+
+/// Response status line and headers, parsed from the bytes before the body.
+struct Head {
+    status: u16,
+    location: Option<String>,
+    chunked: bool,
+}
+
+fn parse_head(head: &str) -> Result<Head> {
+    let mut lines = head.lines();
+    let status = lines
+        .next()
+        .and_then(|l| l.split_whitespace().nth(1))
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| anyhow!("Malformed HTTP response"))?;
+    let headers: Vec<(&str, &str)> = lines.filter_map(|l| l.split_once(':')).collect();
+    let header = |name: &str| headers.iter().find(|(n, _)| n.trim().eq_ignore_ascii_case(name)).map(|(_, v)| v.trim());
+    let location = header("location").map(str::to_string);
+    let chunked = header("transfer-encoding").is_some_and(|v| v.to_ascii_lowercase().contains("chunked"));
+    Ok(Head { status, location, chunked })
+}
+
+/// Counts the payload bytes of a `Transfer-Encoding: chunked` body as it arrives,
+/// skipping the chunk size lines and their CRLFs.
+#[derive(Default)]
+struct ChunkedCounter {
+    /// Data bytes left in the current chunk
+    remaining: usize,
+    /// The chunk size line read so far
+    line: Vec<u8>,
+    /// The last (zero-size) chunk was seen
+    done: bool,
+}
+
+impl ChunkedCounter {
+    /// Feed raw body bytes; returns how many of them are payload.
+    fn feed(&mut self, mut bytes: &[u8]) -> usize {
+        let mut payload = 0;
+        while !bytes.is_empty() && !self.done {
+            if self.remaining > 0 {
+                let n = self.remaining.min(bytes.len());
+                payload += n;
+                self.remaining -= n;
+                bytes = &bytes[n..];
+                continue;
+            }
+            let Some(newline) = bytes.iter().position(|&b| b == b'\n') else {
+                self.line.extend_from_slice(bytes);
+                break;
+            };
+            self.line.extend_from_slice(&bytes[..newline]);
+            bytes = &bytes[newline + 1..];
+            let line = String::from_utf8_lossy(&self.line).to_string();
+            self.line.clear();
+            // The CRLF after a chunk's data reads as an empty line
+            let size = line.split(';').next().unwrap_or("").trim();
+            if size.is_empty() {
+                continue;
+            }
+            match usize::from_str_radix(size, 16) {
+                Ok(0) | Err(_) => self.done = true,
+                Ok(n) => self.remaining = n,
+            }
+        }
+        payload
+    }
+}
+
+/// GET `window` of `url` over a fresh connection, timing DNS, TCP connect, TLS,
+/// TTFB and transfer separately. The whole request, redirects included, gets
+/// `max_time`; the body is read until the window is full or time runs out.
+/// Redirects are followed; the timings are those of the final hop.
+pub async fn timed_get(url: &str, window: ByteWindow, max_time: Duration) -> Result<HttpTimings> {
+    let deadline = Deadline::now() + max_time;
+    let mut url = Url::parse(url).context("Invalid URL")?;
+    for _ in 0..=MAX_REDIRECTS {
+        let host = url.host_str().ok_or_else(|| anyhow!("URL has no host"))?.to_string();
+        let port = url.port_or_known_default().ok_or_else(|| anyhow!("Unsupported URL scheme"))?;
+        // IPv6 literals come in brackets (`[::1]`), which only the Host header wants
+        let ip = host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().ok();
+
+        let start = Instant::now();
+        let addr = match ip {
+            Some(ip) => SocketAddr::new(ip, port),
+            None => phase("DNS lookup", deadline, tokio::net::lookup_host((host.as_str(), port)))
+                .await?
+                .next()
+                .ok_or_else(|| anyhow!("{} has no addresses", host))?,
+        };
+        let dns = start.elapsed();
+
+        let start = Instant::now();
+        let tcp = phase("TCP connect", deadline, TcpStream::connect(addr)).await?;
+        let connect = start.elapsed();
+
+        let (mut conn, tls): (Box<dyn Connection>, _) = match url.scheme() {
+            "https" => {
+                let name = match ip {
+                    Some(ip) => ServerName::IpAddress(ip.into()),
+                    None => ServerName::try_from(host.clone()).context("Invalid TLS server name")?,
+                };
+                let start = Instant::now();
+                let stream = phase("TLS handshake", deadline, tls_connector()?.connect(name, tcp)).await?;
+                (Box::new(stream), Some(start.elapsed()))
+            }
+            "http" => (Box::new(tcp), None),
+            other => return Err(anyhow!("Unsupported URL scheme: {}", other)),
+        };
+
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        let host_header = match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.clone(),
+        };
+        let range_end = window.offset + window.len as u64 - 1;
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: dns-master\r\nAccept-Encoding: identity\r\nRange: bytes={}-{}\r\nConnection: close\r\n\r\n",
+            path, host_header, window.offset, range_end
+        );
+        let start = Instant::now();
+        phase("Sending request", deadline, conn.write_all(request.as_bytes())).await?;
+
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut received = Vec::new();
+        let n = phase("Waiting for response", deadline, conn.read(&mut buf)).await?;
+        let ttfb = start.elapsed();
+        let first_byte = Instant::now();
+        received.extend_from_slice(&buf[..n]);
+
+        let header_end = loop {
+            if let Some(i) = received.windows(4).position(|w| w == b"\r\n\r\n") {
+                break i + 4;
+            }
+            if received.len() > MAX_HEADER_BYTES || n == 0 {
+                return Err(anyhow!("Malformed HTTP response"));
+            }
+            let n = phase("Reading headers", deadline, conn.read(&mut buf)).await?;
+            if n == 0 {
+                return Err(anyhow!("Connection closed before headers"));
+            }
+            received.extend_from_slice(&buf[..n]);
+        };
+        let head = parse_head(&String::from_utf8_lossy(&received[..header_end]))?;
+
+        if (300..400).contains(&head.status) {
+            let location = head.location.ok_or_else(|| anyhow!("Redirect without Location"))?;
+            url = url.join(&location).context("Invalid redirect")?;
+            continue;
+        }
+        if head.status == 416 {
+            return Err(RangeNotSatisfiable { offset: window.offset }.into());
+        }
+        if !(200..300).contains(&head.status) {
+            return Err(anyhow!("Failed to connect: HTTP {}", head.status));
+        }
+        let range_ignored = head.status != 206;
+
+        let mut chunked = head.chunked.then(ChunkedCounter::default);
+        let mut count = |bytes: &[u8]| match chunked.as_mut() {
+            Some(counter) => counter.feed(bytes),
+            None => bytes.len(),
+        };
+        let mut body_bytes = count(&received[header_end..]);
+        while body_bytes < window.len && Deadline::now() < deadline {
+            let n = match tokio::time::timeout_at(deadline, conn.read(&mut buf)).await {
+                Ok(Ok(n)) => n,
+                // Out of time: keep what arrived so far
+                Err(_) => break,
+                // Servers often close TLS without close_notify once the body is sent
+                Ok(Err(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => 0,
+                Ok(Err(e)) => return Err(e).context("Reading body failed"),
+            };
+            if n == 0 {
+                break;
+            }
+            body_bytes += count(&buf[..n]);
+        }
+        let body_bytes = body_bytes.min(window.len);
+        let elapsed = first_byte.elapsed().as_secs_f64();
+        let transfer_mbps = (body_bytes as f64 * 8.0) / (elapsed * 1_000_000.0);
+
+        return Ok(HttpTimings { dns, connect, tls, ttfb, transfer_mbps, bytes: body_bytes, range_ignored });
+    }
+    Err(anyhow!("Too many redirects"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Answer every connection with `head`, then `body` split in two with `pause` in between.
+    async fn stub_server(listener: TcpListener, head: &'static str, body: Vec<u8>, pause: Duration) {
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let body = body.clone();
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut byte = [0u8; 1];
+                    while !request.ends_with(b"\r\n\r\n") && stream.read(&mut byte).await.unwrap_or(0) == 1 {
+                        request.push(byte[0]);
+                    }
+                    let _ = stream.write_all(head.as_bytes()).await;
+                    let _ = stream.write_all(&body[..body.len() / 2]).await;
+                    tokio::time::sleep(pause).await;
+                    let _ = stream.write_all(&body[body.len() / 2..]).await;
+                });
+            }
+        });
+    }
+
+    const WINDOW: ByteWindow = ByteWindow { offset: 0, len: 1024 * 1024 };
+
+    #[test]
+    fn chunked_counter_skips_framing() {
+        let body = b"5\r\nhello\r\na;ext=1\r\n0123456789\r\n0\r\n\r\n";
+        // Any split, including inside a size line, gives the same count
+        for split in 0..body.len() {
+            let mut counter = ChunkedCounter::default();
+            let n = counter.feed(&body[..split]) + counter.feed(&body[split..]);
+            assert_eq!(n, 15, "split at {}", split);
+        }
+    }
+
+    #[tokio::test]
+    async fn chunked_response_counts_payload_bytes() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/test.bin", listener.local_addr().unwrap());
+        let mut body = Vec::new();
+        for _ in 0..3 {
+            body.extend_from_slice(b"3e8\r\n");
+            body.extend_from_slice(&[b'x'; 1000]);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(b"0\r\n\r\n");
+        stub_server(listener, "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n", body, Duration::ZERO).await;
+
+        let timings = timed_get(&url, WINDOW, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(timings.bytes, 3000);
+    }
+
+    #[tokio::test]
+    async fn ipv6_literal_host() {
+        let Ok(listener) = TcpListener::bind("[::1]:0").await else {
+            return; // no IPv6 loopback here
+        };
+        let url = format!("http://[::1]:{}/test.bin", listener.local_addr().unwrap().port());
+        stub_server(listener, "HTTP/1.1 206 Partial Content\r\nContent-Length: 100\r\n\r\n", vec![0; 100], Duration::ZERO).await;
+
+        let timings = timed_get(&url, WINDOW, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(timings.bytes, 100);
+    }
+
+    #[tokio::test]
+    async fn stalled_body_stops_at_max_time() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/test.bin", listener.local_addr().unwrap());
+        let head = "HTTP/1.1 206 Partial Content\r\nContent-Length: 2000\r\n\r\n";
+        stub_server(listener, head, vec![0; 2000], Duration::from_secs(30)).await;
+
+        let start = Instant::now();
+        let timings = timed_get(&url, WINDOW, Duration::from_millis(500)).await.unwrap();
+        assert!(start.elapsed() < Duration::from_secs(2), "took {:?}", start.elapsed());
+        assert_eq!(timings.bytes, 1000);
+    }
+}
@@ -4,10 +4,18 @@ mod dns_utils;
 mod ui;
 mod file_loader;
 mod sys_dns;
+mod config_export;
+mod dns_proxy;
+mod watchdog;
+mod fs_utils;
+mod mirror_apply;
+mod registry_bench;
+mod mirror_freshness;
+mod http_timing;
 
 use anyhow::{Result, Context};
-use app::{App, AppState};
-use clap::Parser;
+use app::{App, AppMode, AppState};
+use clap::{Parser, Subcommand};
 use crossterm::{
     event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind},
     execute,
@@ -15,55 +23,230 @@ use crossterm::{
 };
 use ratatui::{backend::CrosstermBackend, Terminal};
 use std::io;
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::time::Duration;
 use tui_input::backend::crossterm::EventHandler;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Initial DNS server IPs
-    #[arg(short, long, value_delimiter = ',')]
+    #[arg(short, long, value_delimiter = ',', global = true)]
     dns: Vec<IpAddr>,
 
     /// Path to a JSON file containing DNS IPs [{"ip": "..."}]
-    #[arg(long)]
+    #[arg(long, global = true)]
     json: Option<String>,
 
     /// Path to a CSV file containing DNS IPs (header "ip" required)
-    #[arg(long)]
+    #[arg(long, global = true)]
     csv: Option<String>,
+
+    /// Only test DNS servers with this catalog tag (e.g. anti-sanction, ad-block, family, public)
+    #[arg(long, global = true)]
+    tag: Option<String>,
+
+    /// Interface or connection to apply DNS to (default: remembered choice, then auto-detect)
+    #[arg(short, long, global = true)]
+    interface: Option<String>,
+
+    /// Restart Docker after writing registry mirrors to daemon.json
+    #[arg(long)]
+    restart_docker: bool,
+
+    /// Image pulled when benchmarking Docker registry mirrors
+    #[arg(long, default_value = registry_bench::DEFAULT_IMAGE)]
+    docker_image: String,
+
+    /// Offset in bytes of the window downloaded from each mirror's test file
+    #[arg(long, default_value_t = 0)]
+    range_offset: u64,
+
+    /// Size in MiB of the window downloaded from each mirror's test file
+    #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u64).range(1..=1024))]
+    range_mib: u64,
+
+    /// Number of concurrent range streams per mirror test
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..=16))]
+    streams: u64,
+
+    /// Prepare mirrors for this distro instead of the detected one (e.g. for another machine or a container image)
+    #[arg(long, value_parser = mirror_utils::Distro::parse_target)]
+    distro: Option<mirror_utils::Distro>,
+
+    /// Also benchmark mirrors from an official list: Arch mirrorlist or status JSON, Ubuntu mirrors.txt, Debian Mirrors.masterlist or Fedora metalink
+    #[arg(long = "import")]
+    imports: Vec<String>,
+
+    /// Only import mirrors in this country (e.g. IR, or Iran for Arch mirrorlists)
+    #[arg(long)]
+    country: Option<String>,
+
+    /// Mirror list (CSV or JSON) layered over the built-in one and those in the config directory
+    #[arg(long)]
+    mirrors: Option<PathBuf>,
+
+    /// Measure mirror sync lag against this upstream instead of the newest mirror (e.g. arch=https://geo.mirror.pkgbuild.com)
+    #[arg(long, value_parser = mirror_freshness::parse_upstream)]
+    upstream: Vec<(mirror_utils::Distro, String)>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Benchmark the given servers and print a resolver config snippet (no TUI)
+    Export {
+        /// Config format to generate
+        #[arg(short, long, value_enum)]
+        format: config_export::ExportFormat,
+
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Run a local DNS forwarder that always uses the fastest of the given servers
+    Serve {
+        /// Address to listen on
+        #[arg(short, long, default_value = "127.0.0.1:53")]
+        listen: SocketAddr,
+
+        /// Seconds between background re-benchmarks of the upstreams
+        #[arg(long, default_value_t = 300, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+
+        /// Maximum number of cached answers
+        #[arg(long, default_value_t = 1024)]
+        cache_size: usize,
+    },
+    /// Keep probing the applied resolver and fail over to the next-best one from the last benchmark
+    Watch {
+        /// Seconds between probes
+        #[arg(long, default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+
+        /// Consecutive failed probes before switching
+        #[arg(long, default_value_t = 3)]
+        failures: u32,
+
+        /// Probes slower than this (milliseconds) count as failed
+        #[arg(long, default_value_t = 500)]
+        max_latency_ms: u64,
+
+        /// Resolver currently in use (default: the last one applied by this tool)
+        #[arg(long)]
+        current: Option<IpAddr>,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
     
-    let mut initial_dns = cli.dns;
+    let mut catalog: Vec<dns_utils::DnsServer> = cli.dns.iter().map(|ip| dns_utils::DnsServer::new(*ip)).collect();
+    let mut dns_sources = Vec::new();
+    if !catalog.is_empty() {
+        dns_sources.push("--dns".to_string());
+    }
     
     if let Some(json_path) = cli.json {
-        let mut ips = file_loader::load_json(&json_path)
+        let mut servers = file_loader::load_json(&json_path)
             .with_context(|| format!("Failed to load DNS from JSON: {}", json_path))?;
-        initial_dns.append(&mut ips);
+        catalog.append(&mut servers);
+        dns_sources.push(json_path);
     }
     
     if let Some(csv_path) = cli.csv {
-        let mut ips = file_loader::load_csv(&csv_path)
+        let mut servers = file_loader::load_csv(&csv_path)
             .with_context(|| format!("Failed to load DNS from CSV: {}", csv_path))?;
-        initial_dns.append(&mut ips);
+        catalog.append(&mut servers);
+        dns_sources.push(csv_path);
+    }
+
+    // Without servers on the command line, use the built-in catalog and the user's lists
+    if dns_sources.is_empty() {
+        let sources = file_loader::dns_sources();
+        catalog = file_loader::load_dns_sources(&sources).context("Failed to load DNS server lists")?;
+        dns_sources = sources.iter().map(|s| s.label()).collect();
+    }
+
+    // The headless commands only get the tagged servers; the TUI starts filtered but can show all
+    let mut initial_dns: Vec<IpAddr> = catalog
+        .iter()
+        .filter(|s| cli.command.is_none() || cli.tag.as_deref().is_none_or(|tag| s.has_tag(tag)))
+        .map(|s| s.ip)
+        .collect();
+    if initial_dns.is_empty()
+        && let Some(tag) = &cli.tag
+    {
+        anyhow::bail!("No DNS servers are tagged '{}'", tag);
     }
     
     // De-duplicate
     initial_dns.sort();
     initial_dns.dedup();
 
+    match cli.command {
+        Some(Command::Export { format, output }) => {
+            return run_export(&initial_dns, &catalog, format, output).await;
+        }
+        Some(Command::Serve { listen, interval, cache_size }) => {
+            return dns_proxy::serve(dns_proxy::ProxyConfig {
+                listen,
+                upstreams: initial_dns.iter().map(|ip| SocketAddr::new(*ip, 53)).collect(),
+                rebenchmark_interval: Duration::from_secs(interval),
+                cache_size,
+            })
+            .await;
+        }
+        Some(Command::Watch { interval, failures, max_latency_ms, current }) => {
+            return watchdog::run(watchdog::WatchdogConfig {
+                interval: Duration::from_secs(interval),
+                max_failures: failures.max(1),
+                max_latency: Duration::from_millis(max_latency_ms),
+                current,
+                interface: cli.interface,
+            })
+            .await;
+        }
+        None => {}
+    }
+
     let mut app = App::new(initial_dns);
+    app.dns_sources = dns_sources;
+    app.set_dns_catalog(catalog);
+    app.tag_filter = cli.tag;
+    app.mirror_sources = file_loader::mirror_sources(cli.mirrors.as_deref());
+    app.selected_interface = cli.interface.or_else(sys_dns::remembered_target);
+    app.restart_docker = cli.restart_docker;
     
     // Load mirrors
-    let mirrors_path = "examples/mirrors.csv";
-    match file_loader::load_mirrors(mirrors_path, app.detected_distro.clone()) {
-        Ok(mirrors) => app.mirrors = mirrors,
-        Err(e) => eprintln!("Warning: Failed to load mirrors: {}", e),
+    if let Some(distro) = cli.distro {
+        app.detected_distro = distro;
+    }
+    for path in &cli.imports {
+        match file_loader::import_mirrors(path, cli.country.as_deref(), &app.system) {
+            Ok(import) => {
+                if let Some(warning) = import.warning {
+                    eprintln!("Warning: {}: {}", path, warning);
+                }
+                app.imported_mirrors.extend(import.mirrors);
+            }
+            Err(e) => eprintln!("Warning: Failed to import mirrors: {:#}", e),
+        }
+    }
+    app.load_mirrors();
+
+    for (distro, url) in &cli.upstream {
+        match mirror_freshness::last_sync(url, distro, app.system.codename_for(distro)).await {
+            Ok(Some(synced)) => {
+                app.upstream_synced.insert(distro.clone(), synced);
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("Warning: Failed to read upstream sync time from {}: {}", url, e),
+        }
     }
 
     // Setup channels for background benchmarking
@@ -74,6 +257,10 @@ async fn main() -> Result<()> {
     app.rx = Some(rx_result);
 
     // Spawn background worker
+    let docker_image = cli.docker_image;
+    let system = app.system.clone();
+    let streams = cli.streams as usize;
+    let window = http_timing::ByteWindow { offset: cli.range_offset, len: (cli.range_mib * 1024 * 1024) as usize };
     tokio::spawn(async move {
         while let Some(target) = rx_target.recv().await {
             match target {
@@ -81,25 +268,47 @@ async fn main() -> Result<()> {
                     let res = dns_utils::run_full_test(ip).await;
                     let _ = tx_result.send(app::TestResult::Dns(res)).await;
                 }
+                app::TestTarget::Verify(ip) => {
+                    // Give the network manager a moment to pick up the change
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    let check = dns_utils::verify_system_resolver(ip).await.map_err(|e| e.to_string());
+                    let _ = tx_result.send(app::TestResult::Verified(ip, check)).await;
+                }
+                app::TestTarget::Dot(servers) => {
+                    let dot = dns_utils::working_dot(&servers).await;
+                    let _ = tx_result.send(app::TestResult::Dot(dot)).await;
+                }
                 app::TestTarget::Mirror(mirror) => {
-                    match mirror_utils::test_mirror_speed(&mirror.url).await {
-                        Ok(speed) => {
-                            let _ = tx_result.send(app::TestResult::Mirror(mirror_utils::MirrorTestResult {
-                                name: mirror.name,
-                                url: mirror.url,
-                                speed_mbps: Some(speed),
-                                error: None,
-                            })).await;
-                        }
-                        Err(e) => {
-                            let _ = tx_result.send(app::TestResult::Mirror(mirror_utils::MirrorTestResult {
-                                name: mirror.name,
-                                url: mirror.url,
-                                speed_mbps: None,
-                                error: Some(e.to_string()),
-                            })).await;
+                    let speed_test = async {
+                        if mirror.distro == mirror_utils::Distro::Docker {
+                            registry_bench::benchmark_registry(&mirror.base_url, &docker_image)
+                                .await
+                                .map(|r| (r.blob_mbps, None, Vec::new(), Some(r)))
+                        } else {
+                            mirror_utils::test_mirror_speed(&mirror.url, window, streams)
+                                .await
+                                .map(|(t, per_stream)| (t.transfer_mbps, Some(t), per_stream, None))
                         }
-                    }
+                    };
+                    let (tested, synced) =
+                        tokio::join!(speed_test, mirror_freshness::last_sync(&mirror.base_url, &mirror.distro, system.codename_for(&mirror.distro)));
+                    let (speed, error, timings, stream_mbps, registry) = match tested {
+                        Ok((speed, timings, per_stream, registry)) => (Some(speed), None, timings, per_stream, registry),
+                        Err(e) => (None, Some(format!("{:#}", e)), None, Vec::new(), None),
+                    };
+                    let _ = tx_result.send(app::TestResult::Mirror(Box::new(mirror_utils::MirrorTestResult {
+                        name: mirror.name,
+                        base_url: mirror.base_url,
+                        distro: mirror.distro,
+                        origin: mirror.origin,
+                        speed_mbps: speed,
+                        error,
+                        timings,
+                        stream_mbps,
+                        registry,
+                        synced: synced.ok().flatten(),
+                        lag: None,
+                    }))).await;
                 }
             }
         }
@@ -131,6 +340,48 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+async fn run_export(
+    servers: &[IpAddr],
+    catalog: &[dns_utils::DnsServer],
+    format: config_export::ExportFormat,
+    output: Option<PathBuf>,
+) -> Result<()> {
+    if servers.is_empty() {
+        anyhow::bail!("No DNS servers given. Use -d, --csv or --json.");
+    }
+
+    let results = dns_utils::benchmark_all(servers).await;
+    if let Err(e) = dns_utils::BenchmarkState::record_results(&results) {
+        eprintln!("Warning: could not save results for the watchdog: {}", e);
+    }
+    let ranked = dns_utils::ranked_servers(&results);
+    if ranked.is_empty() {
+        anyhow::bail!("None of the {} servers passed the test.", servers.len());
+    }
+
+    // Later lists win, as in the TUI
+    let ranked = ranked
+        .into_iter()
+        .map(|ip| catalog.iter().rev().find(|s| s.ip == ip).cloned().unwrap_or_else(|| dns_utils::DnsServer::new(ip)))
+        .collect::<Vec<_>>();
+    // Only the Unbound snippet can forward over DNS-over-TLS
+    let dot = if format == config_export::ExportFormat::Unbound {
+        dns_utils::working_dot(&ranked).await
+    } else {
+        Vec::new()
+    };
+    let snippet = config_export::render(format, &ranked, &dot);
+    match output {
+        Some(path) => {
+            std::fs::write(&path, snippet)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            eprintln!("Wrote {}", path.display());
+        }
+        None => print!("{}", snippet),
+    }
+    Ok(())
+}
+
 async fn run_app(
     terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
     mut app: App,
@@ -144,12 +395,12 @@ async fn run_app(
         // Handle testing state specially - results are handled in app.update()
         if app.state == AppState::Testing {
             // Poll for events frequently
-            if event::poll(Duration::from_millis(5))? {
-                if let Event::Key(key) = event::read()? {
-                    if key.kind == KeyEventKind::Press && key.code == KeyCode::Char('q') {
-                        app.should_quit = true;
-                    }
-                }
+            if event::poll(Duration::from_millis(5))?
+                && let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+                && key.code == KeyCode::Char('q')
+            {
+                app.should_quit = true;
             }
 
             if app.should_quit {
@@ -162,57 +413,205 @@ async fn run_app(
             continue;
         }
 
+        // Pick up background results such as the post-apply DNS check
+        app.update();
+
         // Normal state - block with timeout for animations
-        if event::poll(tick_rate)? {
-            if let Event::Key(key) = event::read()? {
-                if key.kind != KeyEventKind::Press {
-                    continue;
-                }
+        if event::poll(tick_rate)?
+            && let Event::Key(key) = event::read()?
+        {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
 
-                match app.state {
-                    AppState::Input => match key.code {
-                        KeyCode::Char('q') => {
-                            return Ok(());
-                        }
-                        KeyCode::Enter => {
-                            app.add_dns_server();
-                        }
-                        KeyCode::Tab => {
-                            app.start_testing();
-                        }
-                        KeyCode::Char('m') => {
-                            app.toggle_mode();
-                        }
-                        KeyCode::Backspace => {
-                            app.remove_last_dns_server();
-                        }
-                        _ => {
-                            app.input.handle_event(&Event::Key(key));
-                        }
+            match app.state {
+                AppState::Input if app.mode == AppMode::Mirror => match key.code {
+                    KeyCode::Char('q') => {
+                        return Ok(());
+                    }
+                    KeyCode::Tab => {
+                        app.start_testing();
+                    }
+                    KeyCode::Char('m') => {
+                        app.toggle_mode();
+                    }
+                    KeyCode::Char('t') => {
+                        app.open_distro_picker();
+                    }
+                    KeyCode::Up | KeyCode::Char('k') => {
+                        app.move_mirror_selection(false);
+                    }
+                    KeyCode::Down | KeyCode::Char('j') => {
+                        app.move_mirror_selection(true);
+                    }
+                    KeyCode::Char('a') => {
+                        app.open_mirror_form(false);
+                    }
+                    KeyCode::Enter | KeyCode::Char('e') => {
+                        app.open_mirror_form(true);
+                    }
+                    KeyCode::Char('x') | KeyCode::Delete => {
+                        app.remove_selected_mirror();
+                    }
+                    _ => {}
+                },
+                AppState::Input => match key.code {
+                    KeyCode::Char('q') => {
+                        return Ok(());
+                    }
+                    KeyCode::Enter => {
+                        app.add_dns_server();
+                    }
+                    KeyCode::Tab => {
+                        app.start_testing();
+                    }
+                    KeyCode::Char('m') => {
+                        app.toggle_mode();
+                    }
+                    KeyCode::Backspace => {
+                        app.remove_last_dns_server();
+                    }
+                    KeyCode::Char('t') => {
+                        app.cycle_tag_filter();
+                    }
+                    _ => {
+                        app.input.handle_event(&Event::Key(key));
+                    }
+                },
+                AppState::Results => match key.code {
+                    KeyCode::Char('q') => {
+                        return Ok(());
+                    }
+                    KeyCode::Char('r') => {
+                        app.reset();
+                    }
+                    KeyCode::Char('s') => {
+                        app.cycle_sort_column();
+                    }
+                    KeyCode::Char('d') => {
+                        app.toggle_sort_direction();
+                    }
+                    KeyCode::Char('a') => match app.mode {
+                        AppMode::Dns => app.apply_fastest_dns(),
+                        AppMode::Mirror => app.plan_mirror_apply(),
                     },
-                    AppState::Results => match key.code {
-                        KeyCode::Char('q') => {
-                            return Ok(());
-                        }
-                        KeyCode::Char('r') => {
-                            app.reset();
-                        }
-                        KeyCode::Char('s') => {
-                            app.cycle_sort_column();
-                        }
-                        KeyCode::Char('d') => {
-                            app.toggle_sort_direction();
-                        }
-                        KeyCode::Char('a') => {
-                            app.apply_fastest_dns();
-                        }
-                        KeyCode::Char('m') => {
-                            app.toggle_mode();
+                    KeyCode::Char('D') if app.mode == AppMode::Mirror => {
+                        app.plan_docker_apply();
+                    }
+                    KeyCode::Char('i') if app.mode == AppMode::Dns => {
+                        app.open_interface_picker();
+                    }
+                    KeyCode::Char('p') if app.mode == AppMode::Dns => {
+                        app.open_split_dns_editor();
+                    }
+                    KeyCode::Char('e') if app.mode == AppMode::Dns => {
+                        app.export_configs();
+                    }
+                    KeyCode::Char('u') if app.mode == AppMode::Mirror => {
+                        app.plan_ecosystem_apply();
+                    }
+                    KeyCode::Up | KeyCode::Char('k') => {
+                        app.move_result_selection(false);
+                    }
+                    KeyCode::Down | KeyCode::Char('j') => {
+                        app.move_result_selection(true);
+                    }
+                    KeyCode::Char('m') => {
+                        app.toggle_mode();
+                    }
+                    _ => {}
+                },
+                AppState::PickInterface => match key.code {
+                    KeyCode::Up | KeyCode::Char('k') => {
+                        app.move_interface_selection(false);
+                    }
+                    KeyCode::Down | KeyCode::Char('j') => {
+                        app.move_interface_selection(true);
+                    }
+                    KeyCode::Enter => {
+                        app.confirm_interface();
+                    }
+                    KeyCode::Esc | KeyCode::Char('q') => {
+                        app.cancel_interface_picker();
+                    }
+                    _ => {}
+                },
+                AppState::PickDistro => match key.code {
+                    KeyCode::Up | KeyCode::Char('k') => {
+                        app.move_distro_selection(false);
+                    }
+                    KeyCode::Down | KeyCode::Char('j') => {
+                        app.move_distro_selection(true);
+                    }
+                    KeyCode::Enter => {
+                        app.confirm_distro();
+                    }
+                    KeyCode::Esc | KeyCode::Char('q') => {
+                        app.cancel_distro_picker();
+                    }
+                    _ => {}
+                },
+                AppState::EditMirror => match key.code {
+                    KeyCode::Esc => {
+                        app.cancel_mirror_form();
+                    }
+                    KeyCode::Enter => {
+                        app.save_mirror_form();
+                    }
+                    KeyCode::Tab | KeyCode::Down => {
+                        app.next_mirror_field(true);
+                    }
+                    KeyCode::BackTab | KeyCode::Up => {
+                        app.next_mirror_field(false);
+                    }
+                    KeyCode::Left | KeyCode::Right if app.mirror_form.field == app::MirrorField::Category => {
+                        app.cycle_mirror_category(key.code == KeyCode::Right);
+                    }
+                    _ => {
+                        if let Some(input) = app.mirror_form.focused_input() {
+                            input.handle_event(&Event::Key(key));
                         }
-                        _ => {}
-                    },
+                    }
+                },
+                AppState::ApplyPreview => match key.code {
+                    KeyCode::Char('y') => {
+                        app.confirm_apply();
+                    }
+                    KeyCode::Char('n') | KeyCode::Esc | KeyCode::Char('q') => {
+                        app.cancel_apply();
+                    }
+                    KeyCode::Up | KeyCode::Char('k') => {
+                        app.scroll_preview(false);
+                    }
+                    KeyCode::Down | KeyCode::Char('j') => {
+                        app.scroll_preview(true);
+                    }
                     _ => {}
-                }
+                },
+                AppState::SplitDns => match key.code {
+                    KeyCode::Esc => {
+                        app.close_split_dns_editor();
+                    }
+                    KeyCode::Up => {
+                        app.move_result_selection(false);
+                    }
+                    KeyCode::Down => {
+                        app.move_result_selection(true);
+                    }
+                    KeyCode::Enter => {
+                        app.add_split_dns_route();
+                    }
+                    KeyCode::Tab => {
+                        app.apply_split_dns();
+                    }
+                    KeyCode::Backspace if app.input.value().is_empty() => {
+                        app.remove_last_split_dns_route();
+                    }
+                    _ => {
+                        app.input.handle_event(&Event::Key(key));
+                    }
+                },
+                _ => {}
             }
         }
         
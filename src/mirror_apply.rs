@@ -0,0 +1,892 @@
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::fs_utils;
+use crate::mirror_utils::{ranked_mirrors, Distro, MirrorTestResult};
+
+pub const PACMAN_MIRRORLIST: &str = "etc/pacman.d/mirrorlist";
+pub const APT_SOURCES_LIST: &str = "etc/apt/sources.list";
+pub const APT_SOURCES_DIR: &str = "etc/apt/sources.list.d";
+pub const DOCKER_DAEMON_JSON: &str = "etc/docker/daemon.json";
+pub const DOCKER_RESTART_HINT: &str = "sudo systemctl restart docker";
+// Hosts of the distros' own APT archives; their subdomains count too (`ir.archive.ubuntu.com`)
+const OFFICIAL_APT_HOSTS: [&str; 6] =
+    ["archive.ubuntu.com", "ports.ubuntu.com", "debian.org", "kali.org", "kali.download", "packages.linuxmint.com"];
+// Above this many lines on either side, the preview falls back to a plain remove/add listing
+const MAX_DIFF_LINES: usize = 4000;
+
+/// A planned rewrite of one configuration file.
+#[derive(Debug, Clone)]
+pub struct FileChange {
+    pub path: PathBuf,
+    pub old: String,
+    pub new: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiffLine {
+    Same(String),
+    Added(String),
+    Removed(String),
+}
+
+impl FileChange {
+    /// Line diff of the old and new contents, for the preview.
+    pub fn diff(&self) -> Vec<DiffLine> {
+        diff_lines(&self.old, &self.new)
+    }
+}
+
+/// Longest-common-subsequence line diff.
+fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+
+    if a.len() > MAX_DIFF_LINES || b.len() > MAX_DIFF_LINES {
+        let mut out: Vec<DiffLine> = a.iter().map(|l| DiffLine::Removed(l.to_string())).collect();
+        out.extend(b.iter().map(|l| DiffLine::Added(l.to_string())));
+        return out;
+    }
+
+    // lcs[i][j] = LCS length of a[i..] and b[j..]
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut out = Vec::new();
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            out.push(DiffLine::Same(a[i].to_string()));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            out.push(DiffLine::Removed(a[i].to_string()));
+            i += 1;
+        } else {
+            out.push(DiffLine::Added(b[j].to_string()));
+            j += 1;
+        }
+    }
+    out.extend(a[i..].iter().map(|l| DiffLine::Removed(l.to_string())));
+    out.extend(b[j..].iter().map(|l| DiffLine::Added(l.to_string())));
+    out
+}
+
+/// Write every change, keeping a timestamped backup of each existing file.
+/// Returns the backup paths.
+pub fn apply_changes(changes: &[FileChange]) -> Result<Vec<PathBuf>> {
+    let mut backups = Vec::new();
+    for change in changes {
+        if let Some(dir) = change.path.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        if change.path.exists() {
+            backups.push(fs_utils::backup_timestamped(&change.path)?);
+        }
+        fs_utils::write_atomic(&change.path, &change.new)?;
+    }
+    Ok(backups)
+}
+
+fn read_existing(path: &Path) -> Result<String> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(content),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+/// Plan a pacman mirrorlist from the ranked Arch results, reflector-style:
+/// one `Server = <base>/$repo/os/$arch` line per working mirror, fastest first.
+pub fn plan_pacman_mirrorlist(root: &Path, results: &[MirrorTestResult]) -> Result<FileChange> {
+    let ranked = ranked_mirrors(results, &Distro::Arch);
+    if ranked.is_empty() {
+        return Err(anyhow!("No working Arch mirrors to write"));
+    }
+
+    let mut new = String::from("##\n## Arch Linux repository mirrorlist\n## Generated by DNS Master, ranked by measured download speed\n##\n\n");
+    for mirror in ranked {
+        new.push_str(&format!("## {} ({:.2} Mbps)\n", mirror.name, mirror.speed_mbps.unwrap_or(0.0)));
+        new.push_str(&format!("Server = {}/$repo/os/$arch\n", mirror.base_url));
+    }
+
+    let path = root.join(PACMAN_MIRRORLIST);
+    let old = read_existing(&path)?;
+    Ok(FileChange { path, old, new })
+}
+
+/// Archive name an APT URI points at: its last path segment (`.../ubuntu` -> `ubuntu`).
+/// `None` for security repositories, which must keep their own URIs.
+pub fn apt_archive_name(uri: &str) -> Option<String> {
+    let url = reqwest::Url::parse(uri).ok()?;
+    let host = url.host_str()?;
+    if host.starts_with("security.") || url.path().contains("-security") {
+        return None;
+    }
+    // PPAs end in the distro name too, but are not mirrors of its archive
+    if host.ends_with(".launchpad.net") || host.ends_with(".launchpadcontent.net") {
+        return None;
+    }
+    // Mint's own repository lives at the root of its host
+    if host == "packages.linuxmint.com" {
+        return Some("linuxmint".to_string());
+    }
+    url.path_segments()?
+        .rfind(|s| !s.is_empty())
+        .map(|s| s.to_string())
+}
+
+/// A distro's APT archive: its name (`ubuntu`) and the mirror hosts known to serve it.
+pub struct AptArchive {
+    pub name: String,
+    mirror_hosts: Vec<String>,
+}
+
+impl AptArchive {
+    pub fn new<'a>(name: String, mirror_bases: impl IntoIterator<Item = &'a str>) -> Self {
+        let mirror_hosts = mirror_bases
+            .into_iter()
+            .filter_map(|base| reqwest::Url::parse(base).ok()?.host_str().map(str::to_string))
+            .collect();
+        Self { name, mirror_hosts }
+    }
+
+    /// Whether `uri` points at this archive. Third-party repositories use the same
+    /// layout (`https://packagecloud.io/<org>/<repo>/ubuntu/`), so the host must be
+    /// an official archive host or a known mirror, not just the path.
+    pub fn serves(&self, uri: &str) -> bool {
+        let Some(host) = reqwest::Url::parse(uri).ok().and_then(|u| u.host_str().map(str::to_string)) else {
+            return false;
+        };
+        let official = OFFICIAL_APT_HOSTS.iter().any(|h| host == *h || host.ends_with(&format!(".{}", h)));
+        (official || self.mirror_hosts.contains(&host)) && apt_archive_name(uri).as_deref() == Some(self.name.as_str())
+    }
+}
+
+pub fn is_security_suite(suite: &str) -> bool {
+    suite.ends_with("-security") || suite.ends_with("/updates")
+}
+
+/// One APT source: a one-line `deb`/`deb-src` entry or a deb822 stanza.
+pub struct AptEntry<'a> {
+    pub uris: Vec<&'a str>,
+    pub suites: Vec<&'a str>,
+    pub components: Vec<&'a str>,
+    pub enabled: bool,
+}
+
+impl<'a> AptEntry<'a> {
+    /// The URIs of this entry that point at `archive`, i.e. the ones applying
+    /// repoints at its mirror. Entries with only security suites, or without a
+    /// `main` component, have none: distro archives always carry `main`,
+    /// third-party repos like Docker's `.../linux/ubuntu` do not.
+    pub fn archive_uris(&self, archive: &AptArchive) -> Vec<&'a str> {
+        let security_only = !self.suites.is_empty() && self.suites.iter().all(|s| is_security_suite(s));
+        if security_only || !self.components.contains(&"main") {
+            return Vec::new();
+        }
+        self.uris.iter().copied().filter(|uri| archive.serves(uri)).collect()
+    }
+}
+
+/// Parse a one-line-style `deb [options] uri suite components` entry (or
+/// `deb-src`), returning it with the byte offset of its URI in `line`.
+pub fn parse_legacy_line(line: &str) -> Option<(AptEntry<'_>, usize)> {
+    let trimmed = line.trim_start();
+    let rest = trimmed.strip_prefix("deb-src").or_else(|| trimmed.strip_prefix("deb"))?;
+    if !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let mut rest = rest.trim_start();
+    if rest.starts_with('[') {
+        rest = rest[rest.find(']')? + 1..].trim_start();
+    }
+
+    let mut fields = rest.split_whitespace();
+    let uri = fields.next()?;
+    let suites = fields.next().into_iter().collect();
+    let entry = AptEntry { uris: vec![uri], suites, components: fields.collect(), enabled: true };
+    Some((entry, line.len() - rest.len()))
+}
+
+/// The key and value of a deb822 `field:` line; field names are case-insensitive.
+/// Indented lines continue the previous field and never match.
+pub fn deb822_field<'a>(line: &'a str, field: &str) -> Option<(&'a str, &'a str)> {
+    if line.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let (key, value) = line.split_once(':')?;
+    key.trim().eq_ignore_ascii_case(field).then_some((key, value))
+}
+
+/// Line ranges of the deb822 stanzas in `lines`, which are separated by lines
+/// that are empty or only whitespace.
+pub fn deb822_stanzas(lines: &[&str]) -> Vec<Range<usize>> {
+    let mut stanzas = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        if lines[i].trim().is_empty() {
+            i += 1;
+            continue;
+        }
+        let end = lines[i..].iter().position(|l| l.trim().is_empty()).map_or(lines.len(), |n| i + n);
+        stanzas.push(i..end);
+        i = end;
+    }
+    stanzas
+}
+
+/// Parse a deb822 `.sources` stanza.
+pub fn parse_deb822_stanza<'a>(stanza: &[&'a str]) -> AptEntry<'a> {
+    let field = |name: &str| stanza.iter().find_map(|l| deb822_field(l, name)).map(|(_, value)| value);
+    let words = |name: &str| field(name).map_or(Vec::new(), |value| value.split_whitespace().collect());
+    AptEntry {
+        uris: words("URIs"),
+        suites: words("Suites"),
+        components: words("Components"),
+        enabled: !field("Enabled").is_some_and(|value| value.trim().eq_ignore_ascii_case("no")),
+    }
+}
+
+/// The APT source files under `root`: `sources.list`, then the `*.list` and
+/// deb822 `*.sources` files in `sources.list.d` by name. APT ignores other files there.
+pub fn apt_source_files(root: &Path) -> Vec<PathBuf> {
+    let mut files = vec![root.join(APT_SOURCES_LIST)];
+    if let Ok(entries) = fs::read_dir(root.join(APT_SOURCES_DIR)) {
+        let mut extra: Vec<PathBuf> = entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| matches!(p.extension().and_then(|e| e.to_str()), Some("list") | Some("sources")))
+            .collect();
+        extra.sort();
+        files.extend(extra);
+    }
+    files
+}
+
+/// Whether an APT source file uses the deb822 format.
+pub fn is_deb822(path: &Path) -> bool {
+    path.extension().and_then(|e| e.to_str()) == Some("sources")
+}
+
+/// Rewrite a one-line-style entry if it points at `archive`. Everything but
+/// the URI is kept byte for byte.
+fn rewrite_legacy_line(line: &str, archive: &AptArchive, new_base: &str) -> Option<String> {
+    let (entry, start) = parse_legacy_line(line)?;
+    let uri = *entry.archive_uris(archive).first()?;
+    Some(format!("{}{}{}", &line[..start], new_base, &line[start + uri.len()..]))
+}
+
+/// Rewrite the `URIs:` of every deb822 stanza pointing at `archive`; other
+/// URIs of the stanza are kept.
+fn rewrite_deb822(content: &str, archive: &AptArchive, new_base: &str) -> String {
+    let lines: Vec<&str> = content.lines().collect();
+    let mut out: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+
+    for stanza in deb822_stanzas(&lines) {
+        let ours = parse_deb822_stanza(&lines[stanza.clone()]).archive_uris(archive);
+        if ours.is_empty() {
+            continue;
+        }
+        for i in stanza {
+            if let Some((key, uris)) = deb822_field(lines[i], "URIs") {
+                let uris: Vec<&str> = uris
+                    .split_whitespace()
+                    .map(|uri| if ours.contains(&uri) { new_base } else { uri })
+                    .collect();
+                out[i] = format!("{}: {}", key, uris.join(" "));
+            }
+        }
+    }
+
+    let mut rewritten = out.join("\n");
+    if content.ends_with('\n') {
+        rewritten.push('\n');
+    }
+    rewritten
+}
+
+/// Plan rewrites of every APT source pointing at the same archive as the fastest
+/// `distro` mirror: `sources.list`, `sources.list.d/*.list` and deb822 `*.sources`.
+/// Only URIs on an official archive host or a tested mirror's host are rewritten.
+/// Suites, components, options and security repositories are preserved.
+pub fn plan_apt_sources(root: &Path, distro: &Distro, results: &[MirrorTestResult]) -> Result<Vec<FileChange>> {
+    let best = ranked_mirrors(results, distro)
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("No working {} mirrors to use", distro.as_str()))?;
+    let new_base = best.base_url.clone();
+    let name = apt_archive_name(&new_base)
+        .ok_or_else(|| anyhow!("Cannot tell which archive {} serves", new_base))?;
+    let known = results.iter().filter(|r| &r.distro == distro).map(|r| r.base_url.as_str());
+    let archive = AptArchive::new(name, known);
+
+    let mut changes = Vec::new();
+    for path in apt_source_files(root) {
+        let old = read_existing(&path)?;
+        let new = if is_deb822(&path) {
+            rewrite_deb822(&old, &archive, &new_base)
+        } else {
+            let mut new: String = old.lines()
+                .map(|line| rewrite_legacy_line(line, &archive, &new_base).unwrap_or_else(|| line.to_string()))
+                .collect::<Vec<_>>()
+                .join("\n");
+            if old.ends_with('\n') {
+                new.push('\n');
+            }
+            new
+        };
+        if new != old {
+            changes.push(FileChange { path, old, new });
+        }
+    }
+
+    if changes.is_empty() {
+        return Err(anyhow!("No APT sources point at a {} archive; nothing to change", archive.name));
+    }
+    Ok(changes)
+}
+
+/// Plan merging the ranked working Docker registry mirrors into `daemon.json`'s
+/// `registry-mirrors`, fastest first. Other keys and untested mirrors already
+/// listed are kept, mirrors that just failed the benchmark are dropped, and an
+/// existing file that is not a valid JSON object is refused.
+pub fn plan_docker_daemon_json(root: &Path, results: &[MirrorTestResult]) -> Result<FileChange> {
+    let ranked = ranked_mirrors(results, &Distro::Docker);
+    if ranked.is_empty() {
+        return Err(anyhow!("No working Docker registry mirrors to write"));
+    }
+
+    let path = root.join(DOCKER_DAEMON_JSON);
+    let old = read_existing(&path)?;
+    let mut config = if old.trim().is_empty() {
+        serde_json::Value::Object(serde_json::Map::new())
+    } else {
+        serde_json::from_str::<serde_json::Value>(&old)
+            .with_context(|| format!("{} is not valid JSON; fix it before applying", path.display()))?
+    };
+    let object = config
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("{} is not a JSON object", path.display()))?;
+
+    let failed: Vec<&str> = results
+        .iter()
+        .filter(|r| r.distro == Distro::Docker && (r.error.is_some() || r.speed_mbps.is_none()))
+        .map(|r| r.base_url.trim_end_matches('/'))
+        .collect();
+    let mut mirrors: Vec<String> = ranked.iter().map(|m| m.base_url.clone()).collect();
+    if let Some(existing) = object.get("registry-mirrors").and_then(|v| v.as_array()) {
+        for url in existing.iter().filter_map(|v| v.as_str()) {
+            let url = url.trim_end_matches('/').to_string();
+            if !mirrors.contains(&url) && !failed.contains(&url.as_str()) {
+                mirrors.push(url);
+            }
+        }
+    }
+    object.insert("registry-mirrors".to_string(), serde_json::json!(mirrors));
+
+    let mut new = serde_json::to_string_pretty(&config)?;
+    new.push('\n');
+    Ok(FileChange { path, old, new })
+}
+
+/// Restart the Docker daemon so it picks up `daemon.json`.
+pub fn restart_docker() -> Result<()> {
+    let status = Command::new("sudo")
+        .args(["systemctl", "restart", "docker"])
+        .status()
+        .context("Failed to run sudo systemctl restart docker")?;
+    if !status.success() {
+        return Err(anyhow!("systemctl restart docker failed"));
+    }
+    Ok(())
+}
+
+/// Set `key` in a `key=value` file (`.npmrc`, Go's env file), replacing an
+/// existing assignment or appending one.
+fn set_key_value(content: &str, key: &str, value: &str) -> String {
+    let line = format!("{}={}", key, value);
+    let mut found = false;
+    let mut lines: Vec<String> = content
+        .lines()
+        .map(|l| match l.split_once('=') {
+            Some((k, _)) if k.trim() == key => {
+                found = true;
+                line.clone()
+            }
+            _ => l.to_string(),
+        })
+        .collect();
+    if !found {
+        lines.push(line);
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Set `key = value` in `[section]` of an INI-style file (`pip.conf`), adding
+/// the section if it is missing. Other content is kept.
+fn set_ini_value(content: &str, section: &str, key: &str, value: &str) -> String {
+    let header = format!("[{}]", section);
+    let line = format!("{} = {}", key, value);
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+
+    let Some(start) = lines.iter().position(|l| l.trim() == header) else {
+        if lines.last().is_some_and(|l| !l.trim().is_empty()) {
+            lines.push(String::new());
+        }
+        lines.extend([header, line]);
+        return lines.join("\n") + "\n";
+    };
+    let end = lines[start + 1..]
+        .iter()
+        .position(|l| l.trim_start().starts_with('['))
+        .map_or(lines.len(), |i| start + 1 + i);
+    let existing = lines[start + 1..end]
+        .iter()
+        .position(|l| l.split_once('=').is_some_and(|(k, _)| k.trim() == key));
+    match existing {
+        Some(i) => lines[start + 1 + i] = line,
+        None => {
+            // Insert after the section's last non-blank line
+            let at = (start + 1..end).rev().find(|&i| !lines[i].trim().is_empty()).map_or(start + 1, |i| i + 1);
+            lines.insert(at, line);
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Replace crates.io with the sparse registry at `registry` in a cargo `config.toml`.
+/// Edited as TOML, so `[source.crates-io]` tables, dotted keys and inline tables
+/// are all updated in place; a file that is not valid TOML is refused.
+fn set_cargo_source(content: &str, registry: &str) -> Result<String> {
+    const MIRROR: &str = "dns-master-mirror";
+    let mut doc = content.parse::<toml_edit::DocumentMut>().context("Not valid TOML; fix it before applying")?;
+    let table_like = |item: Option<&toml_edit::Item>| item.is_none_or(|i| i.is_table_like());
+    let source = doc.get("source");
+    if !table_like(source)
+        || !table_like(source.and_then(|s| s.get("crates-io")))
+        || !table_like(source.and_then(|s| s.get(MIRROR)))
+    {
+        return Err(anyhow!("`source` entries must be tables; not touching it"));
+    }
+    // Missing tables are added as `[source.*]` headers rather than inline tables
+    if doc.get("source").is_none() {
+        let mut source = toml_edit::Table::new();
+        source.set_implicit(true);
+        doc.insert("source", toml_edit::Item::Table(source));
+    }
+    for name in ["crates-io", MIRROR] {
+        if doc["source"].get(name).is_none() {
+            doc["source"][name] = toml_edit::Item::Table(toml_edit::Table::new());
+        }
+    }
+    doc["source"]["crates-io"]["replace-with"] = toml_edit::value(MIRROR);
+    doc["source"][MIRROR]["registry"] = toml_edit::value(format!("sparse+{}/", registry));
+    Ok(doc.to_string())
+}
+
+/// The file `go env -w` writes to. Asks Go itself, since the location depends on
+/// the OS and `GOENV`; without Go installed, falls back to its Linux default.
+/// `None` when Go's env file is turned off (`GOENV=off`) or unknown.
+fn go_env_file(config_base: &Path) -> Option<PathBuf> {
+    if let Ok(output) = Command::new("go").args(["env", "GOENV"]).output()
+        && output.status.success()
+    {
+        let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        return (!path.is_empty() && path != "off").then(|| PathBuf::from(path));
+    }
+    cfg!(target_os = "linux").then(|| config_base.join("go/env"))
+}
+
+/// Plan user-level package manager configs pointing at the fastest working
+/// registry mirror of each ecosystem: pip (`pip.conf`), npm (`.npmrc`), cargo
+/// (`config.toml` source replacement) and Go (`GOPROXY` in Go's env file).
+/// Ecosystems without a working mirror are skipped.
+pub fn plan_ecosystem_configs(home: &Path, results: &[MirrorTestResult]) -> Result<Vec<FileChange>> {
+    let config_base = crate::file_loader::user_config_base().unwrap_or_else(|| home.join(".config"));
+    let go_env = go_env_file(&config_base);
+    plan_ecosystem_files(home, &config_base, go_env, results)
+}
+
+fn plan_ecosystem_files(
+    home: &Path,
+    config_base: &Path,
+    go_env: Option<PathBuf>,
+    results: &[MirrorTestResult],
+) -> Result<Vec<FileChange>> {
+    let mut changes = Vec::new();
+
+    if let Some(best) = ranked_mirrors(results, &Distro::PyPI).first() {
+        let path = config_base.join("pip/pip.conf");
+        let old = read_existing(&path)?;
+        let new = set_ini_value(&old, "global", "index-url", &format!("{}/simple", best.base_url));
+        changes.push(FileChange { path, old, new });
+    }
+    if let Some(best) = ranked_mirrors(results, &Distro::Npm).first() {
+        let path = home.join(".npmrc");
+        let old = read_existing(&path)?;
+        let new = set_key_value(&old, "registry", &format!("{}/", best.base_url));
+        changes.push(FileChange { path, old, new });
+    }
+    if let Some(best) = ranked_mirrors(results, &Distro::Crates).first() {
+        let path = home.join(".cargo/config.toml");
+        let old = read_existing(&path)?;
+        let new = set_cargo_source(&old, &best.base_url).with_context(|| format!("Cannot update {}", path.display()))?;
+        changes.push(FileChange { path, old, new });
+    }
+    if let Some(best) = ranked_mirrors(results, &Distro::GoProxy).first()
+        && let Some(path) = go_env
+    {
+        let old = read_existing(&path)?;
+        let new = set_key_value(&old, "GOPROXY", &format!("{},direct", best.base_url));
+        changes.push(FileChange { path, old, new });
+    }
+
+    changes.retain(|c| c.old != c.new);
+    if changes.is_empty() {
+        return Err(anyhow!("No working PyPI, npm, crates.io or Go proxy mirrors to configure"));
+    }
+    Ok(changes)
+}
+
+/// Plan the system mirror configuration change for `distro`.
+pub fn plan_for_distro(root: &Path, distro: &Distro, results: &[MirrorTestResult]) -> Result<Vec<FileChange>> {
+    match distro {
+        Distro::Arch => Ok(vec![plan_pacman_mirrorlist(root, results)?]),
+        Distro::Debian | Distro::Ubuntu | Distro::Kali | Distro::Mint => plan_apt_sources(root, distro, results),
+        other => Err(anyhow!("Applying mirrors is not supported for {} yet", other.as_str())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs_utils::TempDir;
+    use crate::mirror_utils::MirrorOrigin;
+
+    fn result(distro: Distro, base_url: &str, speed: f64) -> MirrorTestResult {
+        MirrorTestResult {
+            name: base_url.to_string(),
+            base_url: base_url.to_string(),
+            distro,
+            origin: MirrorOrigin::Catalog,
+            speed_mbps: Some(speed),
+            error: None,
+            timings: None,
+            stream_mbps: Vec::new(),
+            registry: None,
+            synced: None,
+            lag: None,
+        }
+    }
+
+    fn ubuntu_results() -> Vec<MirrorTestResult> {
+        vec![
+            result(Distro::Ubuntu, "https://slow.example.org/ubuntu", 10.0),
+            result(Distro::Ubuntu, "https://mirror.example.ir/ubuntu", 50.0),
+        ]
+    }
+
+    #[test]
+    fn apt_legacy_list_keeps_security_deb_src_and_options() {
+        let root = TempDir::new("apt-legacy");
+        root.write(
+            APT_SOURCES_LIST,
+            "# Main archive\n\
+             deb http://archive.ubuntu.com/ubuntu noble main restricted universe\n\
+             deb-src http://archive.ubuntu.com/ubuntu noble main restricted\n\
+             deb [arch=amd64 signed-by=/usr/share/keyrings/ubuntu-archive-keyring.gpg] http://ir.archive.ubuntu.com/ubuntu noble-updates main\n\
+             deb http://security.ubuntu.com/ubuntu noble-security main\n\
+             deb http://archive.ubuntu.com/ubuntu noble-security universe\n\
+             deb https://ppa.launchpadcontent.net/deadsnakes/ppa/ubuntu noble main\n",
+        );
+
+        let changes = plan_apt_sources(root.path(), &Distro::Ubuntu, &ubuntu_results()).unwrap();
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].path, root.path().join(APT_SOURCES_LIST));
+        assert_eq!(
+            changes[0].new,
+            "# Main archive\n\
+             deb https://mirror.example.ir/ubuntu noble main restricted universe\n\
+             deb-src https://mirror.example.ir/ubuntu noble main restricted\n\
+             deb [arch=amd64 signed-by=/usr/share/keyrings/ubuntu-archive-keyring.gpg] https://mirror.example.ir/ubuntu noble-updates main\n\
+             deb http://security.ubuntu.com/ubuntu noble-security main\n\
+             deb http://archive.ubuntu.com/ubuntu noble-security universe\n\
+             deb https://ppa.launchpadcontent.net/deadsnakes/ppa/ubuntu noble main\n"
+        );
+    }
+
+    #[test]
+    fn apt_deb822_sources_rewrite_uris_by_stanza() {
+        let root = TempDir::new("apt-deb822");
+        // The blank line between stanzas holds whitespace, and the second stanza uses lowercase fields
+        root.write(
+            &format!("{}/ubuntu.sources", APT_SOURCES_DIR),
+            "Types: deb deb-src\n\
+             URIs: http://archive.ubuntu.com/ubuntu/\n\
+             Suites: noble noble-updates noble-backports\n\
+             Components: main restricted universe multiverse\n\
+             Signed-By: /usr/share/keyrings/ubuntu-archive-keyring.gpg\n\
+             \x20\t\n\
+             types: deb\n\
+             uris: http://archive.ubuntu.com/ubuntu/ https://other.example.com/debian\n\
+             suites: noble-proposed\n\
+             components: main\n\
+             \n\
+             Types: deb\n\
+             URIs: http://archive.ubuntu.com/ubuntu/\n\
+             Suites: noble-security\n\
+             Components: main\n",
+        );
+        // Other repositories are left alone and produce no change
+        root.write(&format!("{}/docker.list", APT_SOURCES_DIR), "deb https://download.docker.com/linux/ubuntu noble stable\n");
+
+        let changes = plan_apt_sources(root.path(), &Distro::Ubuntu, &ubuntu_results()).unwrap();
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].path, root.path().join(APT_SOURCES_DIR).join("ubuntu.sources"));
+        assert_eq!(
+            changes[0].new,
+            "Types: deb deb-src\n\
+             URIs: https://mirror.example.ir/ubuntu\n\
+             Suites: noble noble-updates noble-backports\n\
+             Components: main restricted universe multiverse\n\
+             Signed-By: /usr/share/keyrings/ubuntu-archive-keyring.gpg\n\
+             \x20\t\n\
+             types: deb\n\
+             uris: https://mirror.example.ir/ubuntu https://other.example.com/debian\n\
+             suites: noble-proposed\n\
+             components: main\n\
+             \n\
+             Types: deb\n\
+             URIs: http://archive.ubuntu.com/ubuntu/\n\
+             Suites: noble-security\n\
+             Components: main\n"
+        );
+    }
+
+    #[test]
+    fn apt_leaves_third_party_repos_with_distro_layout_alone() {
+        let root = TempDir::new("apt-third-party");
+        let third_party = "deb https://packagecloud.io/github/git-lfs/ubuntu/ noble main\n\
+                           deb [signed-by=/etc/apt/keyrings/gitlab.gpg] https://packages.gitlab.com/gitlab/gitlab-ce/ubuntu/ noble main\n";
+        root.write(&format!("{}/third-party.list", APT_SOURCES_DIR), third_party);
+        root.write(
+            &format!("{}/packagecloud.sources", APT_SOURCES_DIR),
+            "Types: deb\nURIs: https://packagecloud.io/ookla/speedtest-cli/ubuntu/\nSuites: noble\nComponents: main\n",
+        );
+        // A tested mirror's host counts as the archive even though it is not an official one
+        root.write(APT_SOURCES_LIST, "deb https://slow.example.org/ubuntu noble main\n");
+
+        let changes = plan_apt_sources(root.path(), &Distro::Ubuntu, &ubuntu_results()).unwrap();
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].path, root.path().join(APT_SOURCES_LIST));
+        assert_eq!(changes[0].new, "deb https://mirror.example.ir/ubuntu noble main\n");
+    }
+
+    #[test]
+    fn apt_archive_needs_official_or_known_host() {
+        let archive = AptArchive::new("debian".to_string(), ["https://mirror.example.ir/debian"]);
+        assert!(archive.serves("http://deb.debian.org/debian"));
+        assert!(archive.serves("http://ftp.de.debian.org/debian/"));
+        assert!(archive.serves("https://mirror.example.ir/debian"));
+        assert!(!archive.serves("https://packagecloud.io/org/repo/debian/"));
+        assert!(!archive.serves("https://notdebian.org/debian"));
+        assert!(!archive.serves("http://deb.debian.org/debian-security"));
+    }
+
+    #[test]
+    fn apt_without_matching_sources_is_an_error() {
+        let root = TempDir::new("apt-none");
+        root.write(APT_SOURCES_LIST, "deb http://deb.debian.org/debian bookworm main\n");
+
+        let err = plan_apt_sources(root.path(), &Distro::Ubuntu, &ubuntu_results()).unwrap_err();
+        assert!(err.to_string().contains("nothing to change"), "{}", err);
+    }
+
+    fn docker_results() -> Vec<MirrorTestResult> {
+        let mut failed = result(Distro::Docker, "https://down.example.org", 0.0);
+        (failed.speed_mbps, failed.error) = (None, Some("connection refused".to_string()));
+        vec![
+            result(Distro::Docker, "https://docker.example.ir", 30.0),
+            failed,
+            result(Distro::Docker, "https://registry.example.com", 60.0),
+        ]
+    }
+
+    #[test]
+    fn docker_daemon_json_keeps_other_keys_and_drops_failing_mirrors() {
+        let root = TempDir::new("docker-merge");
+        root.write(
+            DOCKER_DAEMON_JSON,
+            r#"{"log-driver": "journald", "insecure-registries": ["10.0.0.5:5000"],
+                "registry-mirrors": ["https://down.example.org/", "https://untested.example.net", "https://docker.example.ir/"]}"#,
+        );
+
+        let change = plan_docker_daemon_json(root.path(), &docker_results()).unwrap();
+
+        let config: serde_json::Value = serde_json::from_str(&change.new).unwrap();
+        assert_eq!(
+            config["registry-mirrors"],
+            serde_json::json!(["https://registry.example.com", "https://docker.example.ir", "https://untested.example.net"])
+        );
+        assert_eq!(config["log-driver"], "journald");
+        assert_eq!(config["insecure-registries"], serde_json::json!(["10.0.0.5:5000"]));
+    }
+
+    #[test]
+    fn docker_daemon_json_refuses_invalid_json() {
+        let root = TempDir::new("docker-invalid");
+        root.write(DOCKER_DAEMON_JSON, "{\"registry-mirrors\": [\"https://a.example\",]}\n");
+        let err = plan_docker_daemon_json(root.path(), &docker_results()).unwrap_err();
+        assert!(format!("{:#}", err).contains("is not valid JSON"), "{:#}", err);
+
+        root.write(DOCKER_DAEMON_JSON, "[\"https://a.example\"]\n");
+        let err = plan_docker_daemon_json(root.path(), &docker_results()).unwrap_err();
+        assert!(err.to_string().contains("is not a JSON object"), "{}", err);
+    }
+
+    #[test]
+    fn pacman_mirrorlist_lists_working_mirrors_fastest_first() {
+        let root = TempDir::new("pacman");
+        root.write(PACMAN_MIRRORLIST, "Server = https://old.example.org/archlinux/$repo/os/$arch\n");
+        let mut failed = result(Distro::Arch, "https://down.example.org/archlinux", 0.0);
+        (failed.speed_mbps, failed.error) = (None, Some("connection refused".to_string()));
+        let mut stale = result(Distro::Arch, "https://stale.example.org/archlinux", 90.0);
+        stale.lag = Some(crate::mirror_freshness::STALE_AFTER * 2);
+        let results = vec![
+            result(Distro::Arch, "https://slow.example.org/archlinux", 12.5),
+            failed,
+            stale,
+            result(Distro::Ubuntu, "https://mirror.example.ir/ubuntu", 80.0),
+            result(Distro::Arch, "https://mirror.example.ir/archlinux", 40.0),
+        ];
+
+        let change = plan_pacman_mirrorlist(root.path(), &results).unwrap();
+
+        assert_eq!(change.path, root.path().join(PACMAN_MIRRORLIST));
+        assert_eq!(change.old, "Server = https://old.example.org/archlinux/$repo/os/$arch\n");
+        let servers: Vec<&str> = change.new.lines().filter(|l| l.starts_with("Server = ")).collect();
+        assert_eq!(
+            servers,
+            [
+                "Server = https://mirror.example.ir/archlinux/$repo/os/$arch",
+                "Server = https://slow.example.org/archlinux/$repo/os/$arch",
+                "Server = https://stale.example.org/archlinux/$repo/os/$arch",
+            ]
+        );
+        assert!(change.new.contains("## https://mirror.example.ir/archlinux (40.00 Mbps)\nServer = "));
+        assert!(!change.new.contains("down.example.org"));
+    }
+
+    #[test]
+    fn pacman_mirrorlist_needs_a_working_mirror() {
+        let root = TempDir::new("pacman-none");
+        let mut failed = result(Distro::Arch, "https://down.example.org/archlinux", 0.0);
+        (failed.speed_mbps, failed.error) = (None, Some("timed out".to_string()));
+
+        assert!(plan_pacman_mirrorlist(root.path(), &[failed]).is_err());
+        assert!(!root.path().join(PACMAN_MIRRORLIST).exists());
+    }
+
+    #[test]
+    fn key_value_replaces_or_appends() {
+        assert_eq!(
+            set_key_value("# npm\nregistry = https://old.example/\nsave-exact=true\n", "registry", "https://new.example/"),
+            "# npm\nregistry=https://new.example/\nsave-exact=true\n"
+        );
+        assert_eq!(set_key_value("save-exact=true", "registry", "https://new.example/"), "save-exact=true\nregistry=https://new.example/\n");
+        assert_eq!(set_key_value("", "GOPROXY", "https://goproxy.example,direct"), "GOPROXY=https://goproxy.example,direct\n");
+    }
+
+    #[test]
+    fn ini_value_is_set_in_its_section_only() {
+        let pip = "[install]\nindex-url = https://install.example/simple\n\n[global]\ntimeout = 60\n\n[freeze]\nall = true\n";
+        assert_eq!(
+            set_ini_value(pip, "global", "index-url", "https://new.example/simple"),
+            "[install]\nindex-url = https://install.example/simple\n\n[global]\ntimeout = 60\nindex-url = https://new.example/simple\n\n[freeze]\nall = true\n"
+        );
+        assert_eq!(
+            set_ini_value("[global]\nindex-url=https://old.example/simple\n", "global", "index-url", "https://new.example/simple"),
+            "[global]\nindex-url = https://new.example/simple\n"
+        );
+        assert_eq!(set_ini_value("[install]\nuser = true", "global", "index-url", "x"), "[install]\nuser = true\n\n[global]\nindex-url = x\n");
+    }
+
+    #[test]
+    fn cargo_source_is_edited_as_toml() {
+        let registry = "https://crates.example.ir/api/v1/crates";
+        let parsed = |toml: &str| toml.parse::<toml_edit::DocumentMut>().unwrap();
+        let check = |new: &str| {
+            let doc = parsed(new);
+            assert_eq!(doc["source"]["crates-io"]["replace-with"].as_str(), Some("dns-master-mirror"), "{}", new);
+            assert_eq!(doc["source"]["dns-master-mirror"]["registry"].as_str(), Some(&*format!("sparse+{}/", registry)), "{}", new);
+        };
+
+        let new = set_cargo_source("", registry).unwrap();
+        check(&new);
+        assert!(!new.contains("[source]\n"), "{}", new);
+
+        let tables = "[build]\njobs = 4\n\n[source.crates-io]\nreplace-with = \"ustc\"\n\n[source.ustc]\nregistry = \"sparse+https://mirrors.ustc.edu.cn/crates.io-index/\"\n";
+        let new = set_cargo_source(tables, registry).unwrap();
+        check(&new);
+        assert_eq!(parsed(&new)["build"]["jobs"].as_integer(), Some(4));
+        assert!(new.contains("[source.ustc]"));
+
+        // Dotted keys and inline tables are updated rather than duplicated
+        let dotted = "source.crates-io.replace-with = \"ustc\"\nsource.ustc.registry = \"sparse+https://mirrors.ustc.edu.cn/crates.io-index/\"\n";
+        let new = set_cargo_source(dotted, registry).unwrap();
+        check(&new);
+        assert_eq!(new.matches("replace-with").count(), 1, "{}", new);
+        let inline = "source = { crates-io = { replace-with = \"ustc\" } }\n";
+        let new = set_cargo_source(inline, registry).unwrap();
+        check(&new);
+        assert_eq!(new.matches("replace-with").count(), 1, "{}", new);
+
+        assert!(set_cargo_source("[source.crates-io\nreplace-with = 1\n", registry).is_err());
+        assert!(set_cargo_source("source = \"ustc\"\n", registry).is_err());
+    }
+
+    #[test]
+    fn ecosystem_configs_cover_pip_npm_cargo_and_go() {
+        let home = TempDir::new("ecosystems");
+        let config_base = home.path().join(".config");
+        home.write(".npmrc", "save-exact=true\nregistry=https://registry.npmjs.org/\n");
+        home.write(".cargo/config.toml", "[net]\ngit-fetch-with-cli = true\n");
+        let go_env = home.path().join(".config/go/env");
+        let results = vec![
+            result(Distro::PyPI, "https://pypi.example.ir", 20.0),
+            result(Distro::PyPI, "https://pypi-fast.example.ir", 40.0),
+            result(Distro::Npm, "https://npm.example.ir", 20.0),
+            result(Distro::Crates, "https://crates.example.ir", 20.0),
+            result(Distro::GoProxy, "https://goproxy.example.ir", 20.0),
+        ];
+
+        let changes = plan_ecosystem_files(home.path(), &config_base, Some(go_env.clone()), &results).unwrap();
+
+        let new = |path: &Path| changes.iter().find(|c| c.path == path).map(|c| c.new.clone()).unwrap();
+        assert_eq!(changes.len(), 4);
+        assert_eq!(new(&config_base.join("pip/pip.conf")), "[global]\nindex-url = https://pypi-fast.example.ir/simple\n");
+        assert_eq!(new(&home.path().join(".npmrc")), "save-exact=true\nregistry=https://npm.example.ir/\n");
+        let cargo = new(&home.path().join(".cargo/config.toml"));
+        assert!(cargo.starts_with("[net]\ngit-fetch-with-cli = true\n"), "{}", cargo);
+        assert!(cargo.contains("registry = \"sparse+https://crates.example.ir/\""), "{}", cargo);
+        assert_eq!(new(&go_env), "GOPROXY=https://goproxy.example.ir,direct\n");
+
+        // Without a Go env file (GOENV=off) Go is skipped; nothing to do at all is an error
+        let changes = plan_ecosystem_files(home.path(), &config_base, None, &results[4..]);
+        assert!(changes.is_err());
+    }
+}
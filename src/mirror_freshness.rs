@@ -0,0 +1,226 @@
+use anyhow::{anyhow, Context, Result};
+use futures_util::StreamExt;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::mirror_utils::{Distro, MirrorTestResult};
+
+/// Mirrors lagging the newest one by more than this are ranked last.
+pub const STALE_AFTER: Duration = Duration::from_secs(24 * 3600);
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// The Date: field is in the first few lines of InRelease
+const MAX_HEADER_BYTES: usize = 16 * 1024;
+
+/// The frequently updated suite whose `InRelease` date tracks a mirror's sync
+/// time, for this system's release `codename` when known.
+fn reference_suite(distro: &Distro, codename: Option<&str>) -> Option<String> {
+    match distro {
+        Distro::Debian => Some(codename.map_or("testing".to_string(), |c| format!("{}-updates", c))),
+        Distro::Ubuntu => Some(format!("{}-updates", codename.unwrap_or("noble"))),
+        Distro::Kali => Some("kali-rolling".to_string()),
+        Distro::Mint => Some(codename.unwrap_or("wilma").to_string()),
+        _ => None,
+    }
+}
+
+/// When the mirror at `base` last synced, as unix seconds. `Ok(None)` for mirror
+/// types without a sync marker (Docker, Android SDK).
+pub async fn last_sync(base: &str, distro: &Distro, codename: Option<&str>) -> Result<Option<u64>> {
+    let base = base.trim_end_matches('/');
+    let url = match distro {
+        Distro::Arch => format!("{}/lastsync", base),
+        Distro::Manjaro => format!("{}/stable/state", base),
+        _ => match reference_suite(distro, codename) {
+            Some(suite) => format!("{}/dists/{}/InRelease", base, suite),
+            None => return Ok(None),
+        },
+    };
+
+    let client = reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build()?;
+    let response = client.get(&url).send().await.context("Sync marker request failed")?;
+    if !response.status().is_success() {
+        return Err(anyhow!("{} returned {}", url, response.status()));
+    }
+
+    let mut body = Vec::new();
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        body.extend_from_slice(&chunk.context("Failed to read sync marker")?);
+        if body.len() >= MAX_HEADER_BYTES {
+            break;
+        }
+    }
+    parse_sync_marker(distro, &String::from_utf8_lossy(&body))
+        .map(Some)
+        .ok_or_else(|| anyhow!("No sync date in {}", url))
+}
+
+/// The sync time in a mirror's sync marker: Arch's `lastsync` (unix seconds),
+/// Manjaro's `state` (`date=` line) or an APT `InRelease` (`Date:` field).
+fn parse_sync_marker(distro: &Distro, body: &str) -> Option<u64> {
+    match distro {
+        Distro::Arch => body.trim().parse().ok(),
+        Distro::Manjaro => body
+            .lines()
+            .find_map(|l| l.strip_prefix("date="))
+            .and_then(parse_iso8601),
+        _ => body
+            .lines()
+            .find_map(|l| l.strip_prefix("Date:"))
+            .and_then(parse_http_date),
+    }
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let month = month as i64;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn to_unix(year: i64, month: u32, day: u32, time: &str) -> Option<u64> {
+    let mut hms = time.split(':').map(|p| p.parse::<i64>().ok());
+    let (h, m, s) = (hms.next()??, hms.next()??, hms.next().unwrap_or(Some(0))?);
+    let secs = days_from_civil(year, month, day) * 86_400 + h * 3600 + m * 60 + s;
+    u64::try_from(secs).ok()
+}
+
+/// Parse an RFC 2822 date in UTC, e.g. `Sat, 18 Oct 2026 12:00:00 UTC`.
+fn parse_http_date(value: &str) -> Option<u64> {
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let parts: Vec<&str> = value.split_whitespace().filter(|p| !p.ends_with(',')).collect();
+    let [day, month, year, time, ..] = parts.as_slice() else {
+        return None;
+    };
+    let month = MONTHS.iter().position(|m| m == month)? as u32 + 1;
+    to_unix(year.parse().ok()?, month, day.parse().ok()?, time)
+}
+
+/// Parse an ISO 8601 UTC timestamp, e.g. `2026-10-18T12:00:00Z`.
+fn parse_iso8601(value: &str) -> Option<u64> {
+    let (date, time) = value.trim().trim_end_matches('Z').split_once('T')?;
+    let mut ymd = date.split('-');
+    let year = ymd.next()?.parse().ok()?;
+    let month = ymd.next()?.parse().ok()?;
+    let day = ymd.next()?.parse().ok()?;
+    to_unix(year, month, day, time)
+}
+
+/// Set each result's lag behind the newest mirror of its type, or behind the
+/// upstream reference for that type when one was given.
+pub fn update_lags(results: &mut [MirrorTestResult], references: &HashMap<Distro, u64>) {
+    let mut newest: HashMap<Distro, u64> = references.clone();
+    for result in results.iter() {
+        if let Some(synced) = result.synced
+            && !references.contains_key(&result.distro)
+        {
+            let entry = newest.entry(result.distro.clone()).or_insert(synced);
+            *entry = (*entry).max(synced);
+        }
+    }
+    for result in results.iter_mut() {
+        result.lag = result
+            .synced
+            .zip(newest.get(&result.distro))
+            .map(|(synced, newest)| Duration::from_secs(newest.saturating_sub(synced)));
+    }
+}
+
+/// Parse a `--upstream` value of the form `arch=https://geo.mirror.pkgbuild.com`.
+pub fn parse_upstream(value: &str) -> Result<(Distro, String)> {
+    let (id, url) = value
+        .split_once('=')
+        .ok_or_else(|| anyhow!("expected DISTRO=URL, e.g. arch=https://geo.mirror.pkgbuild.com"))?;
+    let distro = Distro::from_name(id);
+    if reference_suite(&distro, None).is_none() && !matches!(distro, Distro::Arch | Distro::Manjaro) {
+        return Err(anyhow!("no freshness check for '{}'", id));
+    }
+    Ok((distro, url.trim_end_matches('/').to_string()))
+}
+
+/// A short human-readable lag, e.g. `3h 12m` or `2d 4h`.
+pub fn format_lag(lag: Duration) -> String {
+    let mins = lag.as_secs() / 60;
+    match (mins / 1440, mins / 60 % 24, mins % 60) {
+        (0, 0, m) => format!("{}m", m),
+        (0, h, m) => format!("{}h {}m", h, m),
+        (d, h, _) => format!("{}d {}h", d, h),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mirror_utils::MirrorOrigin;
+
+    #[test]
+    fn sync_markers_parse_to_unix_time() {
+        let cases = [
+            (Distro::Arch, "1792324800\n", Some(1792324800)),
+            (Distro::Arch, "<html>not found</html>", None),
+            (
+                Distro::Ubuntu,
+                "-----BEGIN PGP SIGNED MESSAGE-----\nHash: SHA512\n\nOrigin: Ubuntu\nSuite: noble-updates\n\
+                 Date: Sat, 18 Oct 2026 12:00:00 UTC\nArchitectures: amd64\n",
+                Some(1792324800),
+            ),
+            (Distro::Debian, "Date: Thu, 29 Feb 2024 23:59:59 UTC\n", Some(1709251199)),
+            (Distro::Debian, "Date: Wed, 1 Mar 2000 00:00:00 UTC\n", Some(951868800)),
+            (Distro::Kali, "Date: Fri, 31 Dec 1999 8:30 UTC\n", Some(946629000)),
+            (Distro::Debian, "Date: 18 Foo 2026 12:00:00 UTC\n", None),
+            (Distro::Manjaro, "state=7e1f0c5\ndate=2026-10-18T12:00:00Z\n", Some(1792324800)),
+            (Distro::Manjaro, "date=2024-02-29T23:59:59\n", Some(1709251199)),
+            (Distro::Manjaro, "date=yesterday\n", None),
+        ];
+        for (distro, body, expected) in cases {
+            assert_eq!(parse_sync_marker(&distro, body), expected, "{:?}: {:?}", distro, body);
+        }
+    }
+
+    fn synced(distro: Distro, name: &str, synced: Option<u64>) -> MirrorTestResult {
+        MirrorTestResult {
+            name: name.to_string(),
+            base_url: format!("https://{}", name),
+            distro,
+            origin: MirrorOrigin::Catalog,
+            speed_mbps: Some(10.0),
+            error: None,
+            timings: None,
+            stream_mbps: Vec::new(),
+            registry: None,
+            synced,
+            lag: None,
+        }
+    }
+
+    #[test]
+    fn lag_is_measured_against_the_newest_mirror_of_each_type() {
+        let mut results = vec![
+            synced(Distro::Arch, "a", Some(1_000_000)),
+            synced(Distro::Arch, "b", Some(1_000_000 - 3 * 86_400)),
+            synced(Distro::Ubuntu, "c", Some(900_000)),
+            synced(Distro::Arch, "d", None),
+        ];
+        update_lags(&mut results, &HashMap::new());
+
+        let lags: Vec<_> = results.iter().map(|r| r.lag).collect();
+        assert_eq!(lags, [Some(Duration::ZERO), Some(Duration::from_secs(3 * 86_400)), Some(Duration::ZERO), None]);
+        assert!(results[1].is_stale() && !results[0].is_stale());
+    }
+
+    #[test]
+    fn lag_is_measured_against_the_upstream_when_given() {
+        let mut results = vec![synced(Distro::Arch, "a", Some(1_000_000)), synced(Distro::Ubuntu, "c", Some(900_000))];
+        // A mirror newer than the upstream reference has no lag rather than a negative one
+        let references = HashMap::from([(Distro::Arch, 1_000_000 + 7200), (Distro::Ubuntu, 800_000)]);
+        update_lags(&mut results, &references);
+
+        assert_eq!(results[0].lag, Some(Duration::from_secs(7200)));
+        assert_eq!(results[1].lag, Some(Duration::ZERO));
+    }
+}
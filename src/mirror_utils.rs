@@ -1,9 +1,11 @@
-use anyhow::{Result, Context};
+use anyhow::Result;
+use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
 use serde::Deserialize;
-use std::time::Duration;
-use tokio::time::timeout;
+use std::time::{Duration, Instant};
+
+use crate::http_timing::{self, ByteWindow, HttpTimings};
+use crate::registry_bench::RegistryTimings;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
 pub enum Distro {
@@ -13,12 +15,70 @@ pub enum Distro {
     Kali,
     Mint,
     Manjaro,
+    Fedora,
+    OpenSuse,
+    Alpine,
+    Gentoo,
+    Void,
+    NixOS,
+    Rocky,
+    Alma,
     Docker,
     AndroidSDK,
+    PyPI,
+    Npm,
+    Crates,
+    GoProxy,
+    Maven,
+    Homebrew,
     Unknown,
 }
 
 impl Distro {
+    /// Operating systems mirrors can be prepared for (excludes global services).
+    pub const TARGETS: [Distro; 14] = [
+        Distro::Arch,
+        Distro::Debian,
+        Distro::Ubuntu,
+        Distro::Kali,
+        Distro::Mint,
+        Distro::Manjaro,
+        Distro::Fedora,
+        Distro::OpenSuse,
+        Distro::Alpine,
+        Distro::Gentoo,
+        Distro::Void,
+        Distro::NixOS,
+        Distro::Rocky,
+        Distro::Alma,
+    ];
+
+    /// Every kind of mirror a list entry can be: the OS distros, then the global services.
+    pub const CATEGORIES: [Distro; 22] = [
+        Distro::Arch,
+        Distro::Debian,
+        Distro::Ubuntu,
+        Distro::Kali,
+        Distro::Mint,
+        Distro::Manjaro,
+        Distro::Fedora,
+        Distro::OpenSuse,
+        Distro::Alpine,
+        Distro::Gentoo,
+        Distro::Void,
+        Distro::NixOS,
+        Distro::Rocky,
+        Distro::Alma,
+        Distro::Docker,
+        Distro::AndroidSDK,
+        Distro::PyPI,
+        Distro::Npm,
+        Distro::Crates,
+        Distro::GoProxy,
+        Distro::Maven,
+        Distro::Homebrew,
+    ];
+
     pub fn from_id(id: &str) -> Self {
         match id.to_lowercase().as_str() {
             "arch" => Distro::Arch,
@@ -27,10 +87,67 @@ impl Distro {
             "kali" => Distro::Kali,
             "linuxmint" => Distro::Mint,
             "manjaro" => Distro::Manjaro,
+            "fedora" => Distro::Fedora,
+            "alpine" => Distro::Alpine,
+            "gentoo" => Distro::Gentoo,
+            "void" => Distro::Void,
+            "nixos" => Distro::NixOS,
+            // RHEL, CentOS Stream and Oracle Linux say `ID_LIKE=fedora`, but their repos are
+            // laid out like Rocky's (BaseOS by major version), not Fedora's
+            "rocky" | "rhel" | "centos" | "ol" => Distro::Rocky,
+            "almalinux" => Distro::Alma,
+            // opensuse-tumbleweed, opensuse-leap, opensuse-slowroll
+            id if id.starts_with("opensuse") => Distro::OpenSuse,
             _ => Distro::Unknown,
         }
     }
 
+    /// Services loaded whatever the target distro is: Docker, the Android SDK
+    /// and language package registries.
+    pub fn is_global_service(&self) -> bool {
+        matches!(
+            self,
+            Distro::Docker
+                | Distro::AndroidSDK
+                | Distro::PyPI
+                | Distro::Npm
+                | Distro::Crates
+                | Distro::GoProxy
+                | Distro::Maven
+                | Distro::Homebrew
+        )
+    }
+
+    /// Parse a `--distro` value; only [`Distro::TARGETS`] are accepted.
+    pub fn parse_target(name: &str) -> std::result::Result<Self, String> {
+        let distro = Distro::from_name(name);
+        if Distro::TARGETS.contains(&distro) {
+            Ok(distro)
+        } else {
+            let names: Vec<&str> = Distro::TARGETS.iter().map(|d| d.as_str()).collect();
+            Err(format!("unknown distro '{}' (expected one of: {})", name, names.join(", ")))
+        }
+    }
+
+    /// Parse the `distro` column of a mirror list, e.g. `Mint`, `openSUSE` or `AndroidSDK`.
+    /// os-release IDs are accepted too.
+    pub fn from_name(name: &str) -> Self {
+        match name.to_lowercase().replace([' ', '-'], "").as_str() {
+            "mint" => Distro::Mint,
+            "opensuse" => Distro::OpenSuse,
+            "alma" => Distro::Alma,
+            "docker" => Distro::Docker,
+            "androidsdk" => Distro::AndroidSDK,
+            "pypi" => Distro::PyPI,
+            "npm" => Distro::Npm,
+            "crates" | "crates.io" => Distro::Crates,
+            "goproxy" | "go" => Distro::GoProxy,
+            "maven" => Distro::Maven,
+            "homebrew" | "brew" => Distro::Homebrew,
+            _ => Distro::from_id(name),
+        }
+    }
+
     pub fn as_str(&self) -> &str {
         match self {
             Distro::Arch => "Arch",
@@ -39,8 +156,22 @@ impl Distro {
             Distro::Kali => "Kali",
             Distro::Mint => "Mint",
             Distro::Manjaro => "Manjaro",
+            Distro::Fedora => "Fedora",
+            Distro::OpenSuse => "openSUSE",
+            Distro::Alpine => "Alpine",
+            Distro::Gentoo => "Gentoo",
+            Distro::Void => "Void",
+            Distro::NixOS => "NixOS",
+            Distro::Rocky => "Rocky",
+            Distro::Alma => "AlmaLinux",
             Distro::Docker => "Docker",
             Distro::AndroidSDK => "Android SDK",
+            Distro::PyPI => "PyPI",
+            Distro::Npm => "npm",
+            Distro::Crates => "crates.io",
+            Distro::GoProxy => "Go proxy",
+            Distro::Maven => "Maven",
+            Distro::Homebrew => "Homebrew",
             Distro::Unknown => "Unknown",
         }
     }
@@ -53,76 +184,548 @@ impl Distro {
             Distro::Kali => "🐉",
             Distro::Mint => "🍃",
             Distro::Manjaro => "💚",
+            Distro::Fedora => "🎩",
+            Distro::OpenSuse => "🦎",
+            Distro::Alpine => "⛰️",
+            Distro::Gentoo => "🐮",
+            Distro::Void => "🌑",
+            Distro::NixOS => "❄️",
+            Distro::Rocky => "🪨",
+            Distro::Alma => "🌸",
             Distro::Docker => "🐳",
             Distro::AndroidSDK => "🤖",
+            Distro::PyPI => "🐍",
+            Distro::Npm => "📦",
+            Distro::Crates => "🦀",
+            Distro::GoProxy => "🐹",
+            Distro::Maven => "☕",
+            Distro::Homebrew => "🍺",
             Distro::Unknown => "❓",
         }
     }
 }
 
+/// Validate a mirror root URL typed by the user: absolute `http(s)` with a
+/// host and no query. Returns it without the trailing slash.
+pub fn parse_mirror_url(input: &str) -> Result<String> {
+    let url = reqwest::Url::parse(input.trim()).map_err(|e| anyhow::anyhow!("Invalid URL: {}", e))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(anyhow::anyhow!("Mirror URL must start with http:// or https://"));
+    }
+    if url.host_str().is_none_or(str::is_empty) {
+        return Err(anyhow::anyhow!("Mirror URL has no host"));
+    }
+    if url.query().is_some() || url.fragment().is_some() {
+        return Err(anyhow::anyhow!("Mirror URL must be the repository root, without ?query or #fragment"));
+    }
+    Ok(url.as_str().trim_end_matches('/').to_string())
+}
+
+/// Where a mirror in the test run came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+pub enum MirrorOrigin {
+    /// The mirror list CSV
+    #[default]
+    Catalog,
+    /// An official list passed with `--import`
+    Imported,
+    /// The system's own pacman/APT/Docker config, tested as a baseline
+    Current,
+}
+
 #[derive(Debug, Clone, serde::Deserialize)]
 pub struct Mirror {
     pub name: String,
+    /// The file downloaded for the speed test
     pub url: String,
+    /// Repository root, as written into system configs
+    pub base_url: String,
     pub distro: Distro,
+    #[serde(default)]
+    pub origin: MirrorOrigin,
+}
+
+impl Mirror {
+    /// Whether both point at the same repository root, ignoring the scheme
+    pub fn same_root(&self, other: &Mirror) -> bool {
+        let root = |m: &Mirror| m.base_url.split_once("://").map_or(m.base_url.clone(), |(_, r)| r.trim_end_matches('/').to_string());
+        self.distro == other.distro && root(self) == root(other)
+    }
+}
+
+impl Distro {
+    /// Probe path below the mirror root used when a mirror list gives none.
+    /// `{arch}`, `{release}` (alias `{codename}`) and `{repo}` are expanded by [`expand_probe`].
+    pub fn default_probe(&self) -> Option<&'static str> {
+        match self {
+            Distro::Arch => Some("iso/latest/archlinux-{arch}.iso"),
+            Distro::Debian | Distro::Ubuntu | Distro::Kali | Distro::Mint => {
+                Some("dists/{release}/{repo}/binary-{arch}/Packages.gz")
+            }
+            Distro::Manjaro => Some("stable/{repo}/{arch}/{repo}.db"),
+            Distro::Fedora => Some("releases/{release}/Everything/{arch}/os/images/install.img"),
+            Distro::OpenSuse => Some("tumbleweed/iso/openSUSE-Tumbleweed-DVD-{arch}-Current.iso"),
+            Distro::Alpine => Some("{release}/{repo}/{arch}/APKINDEX.tar.gz"),
+            Distro::Gentoo => Some("snapshots/gentoo-latest.tar.xz"),
+            Distro::Void => Some("current/{arch}-repodata"),
+            Distro::NixOS => Some("nixos-{release}/nixexprs.tar.xz"),
+            Distro::Rocky | Distro::Alma => Some("{release}/BaseOS/{arch}/os/images/install.img"),
+            Distro::Docker => Some("v2/"),
+            // Package registries: a real metadata document of a popular package
+            Distro::PyPI => Some("simple/requests/"),
+            Distro::Npm => Some("react"),
+            Distro::Crates => Some("se/rd/serde"),
+            Distro::GoProxy => Some("golang.org/x/text/@v/list"),
+            Distro::Maven => Some("org/apache/commons/commons-lang3/3.14.0/commons-lang3-3.14.0.pom"),
+            Distro::Homebrew => Some("api/formula.jws.json"),
+            Distro::AndroidSDK | Distro::Unknown => None,
+        }
+    }
+
+    /// `{release}` when the target is not the running system.
+    fn default_release(&self) -> &'static str {
+        match self {
+            Distro::Debian => "stable",
+            Distro::Ubuntu => "noble",
+            Distro::Kali => "kali-rolling",
+            Distro::Mint => "wilma",
+            Distro::Fedora => "43",
+            Distro::Alpine => "latest-stable",
+            Distro::NixOS => "unstable",
+            Distro::Rocky | Distro::Alma => "9",
+            _ => "",
+        }
+    }
+
+    fn default_repo(&self) -> &'static str {
+        match self {
+            Distro::Arch | Distro::Manjaro => "core",
+            _ => "main",
+        }
+    }
+
+    /// This machine's architecture as the distro's repositories name it.
+    fn arch_name(&self) -> &'static str {
+        let arch = std::env::consts::ARCH;
+        match self {
+            Distro::Debian | Distro::Ubuntu | Distro::Kali | Distro::Mint => match arch {
+                "x86_64" => "amd64",
+                "aarch64" => "arm64",
+                "x86" => "i386",
+                "arm" => "armhf",
+                other => other,
+            },
+            _ => arch,
+        }
+    }
+}
+
+/// Expand the placeholders of a probe path template for mirrors of `distro`.
+/// The release comes from the running system when it belongs to that family.
+pub fn expand_probe(template: &str, distro: &Distro, system: &DetectedSystem) -> String {
+    let release = match distro {
+        _ if &system.family != distro => None,
+        Distro::Debian | Distro::Ubuntu | Distro::Kali | Distro::Mint => system.codename.clone(),
+        // Alpine repos are named v3.20, the dnf repos of Rocky/Alma by major version
+        Distro::Alpine => system.version.as_ref().map(|v| format!("v{}", v.splitn(3, '.').take(2).collect::<Vec<_>>().join("."))),
+        Distro::Rocky | Distro::Alma => system.version.as_ref().and_then(|v| v.split('.').next()).map(str::to_string),
+        Distro::Fedora | Distro::NixOS => system.version.clone(),
+        _ => None,
+    }
+    .unwrap_or_else(|| distro.default_release().to_string());
+
+    template
+        .replace("{arch}", distro.arch_name())
+        .replace("{release}", &release)
+        .replace("{codename}", &release)
+        .replace("{repo}", distro.default_repo())
 }
 
 #[derive(Debug, Clone)]
 pub struct MirrorTestResult {
     pub name: String,
-    #[allow(dead_code)]
-    pub url: String,
+    pub base_url: String,
+    pub distro: Distro,
+    pub origin: MirrorOrigin,
     pub speed_mbps: Option<f64>,
     pub error: Option<String>,
+    // DNS/connect/TLS/TTFB breakdown of the speed test
+    pub timings: Option<HttpTimings>,
+    // Per-stream speeds of a multi-stream test
+    pub stream_mbps: Vec<f64>,
+    // Per-phase timings for Docker registry mirrors
+    pub registry: Option<RegistryTimings>,
+    // Last sync time (unix seconds) and lag behind the newest mirror
+    pub synced: Option<u64>,
+    pub lag: Option<Duration>,
 }
 
-pub fn detect_distro() -> Distro {
-    if Path::new("/etc/os-release").exists() {
-        if let Ok(content) = fs::read_to_string("/etc/os-release") {
-            for line in content.lines() {
-                if line.starts_with("ID=") {
-                    let id = line.trim_start_matches("ID=").trim_matches('"');
-                    return Distro::from_id(id);
-                }
-            }
-        }
+impl MirrorTestResult {
+    pub fn is_stale(&self) -> bool {
+        self.lag.is_some_and(|lag| lag > crate::mirror_freshness::STALE_AFTER)
+    }
+}
+
+/// Ranking order of working mirrors: fresh before stale, then fastest first.
+pub fn rank_order(a: &MirrorTestResult, b: &MirrorTestResult) -> std::cmp::Ordering {
+    a.is_stale().cmp(&b.is_stale()).then(
+        b.speed_mbps
+            .partial_cmp(&a.speed_mbps)
+            .unwrap_or(std::cmp::Ordering::Equal),
+    )
+}
+
+/// Working mirrors of `distro` from `results`, fastest first. Stale mirrors
+/// go after all fresh ones regardless of speed.
+pub fn ranked_mirrors<'a>(results: &'a [MirrorTestResult], distro: &Distro) -> Vec<&'a MirrorTestResult> {
+    let mut ranked: Vec<_> = results
+        .iter()
+        .filter(|r| &r.distro == distro && r.error.is_none() && r.speed_mbps.is_some())
+        .collect();
+    ranked.sort_by(|a, b| rank_order(a, b));
+    ranked
+}
+
+/// Derive a mirror's base URL (the repository root) from a full test file URL
+/// in an old-style mirror list (see [`Distro::default_probe`] for the layouts),
+/// e.g. `https://host/archlinux/iso/latest/archlinux-x86_64.iso` -> `https://host/archlinux`.
+pub fn mirror_base_url(url: &str) -> String {
+    const MARKERS: [&str; 8] = [
+        "/iso/", "/ls-lR.gz", "/v2/", "/releases/", "/latest-stable/", "/snapshots/", "/current/", "/nixos-",
+    ];
+    let base = if let Some(i) = url.find("/BaseOS/") {
+        // Strip the release version before BaseOS too
+        url[..i].rsplit_once('/').map(|(dir, _)| dir).unwrap_or(&url[..i])
+    } else {
+        MARKERS
+            .iter()
+            .find_map(|m| url.find(m).map(|i| &url[..i]))
+            .unwrap_or_else(|| url.rsplit_once('/').map(|(dir, _)| dir).unwrap_or(url))
+    };
+    base.trim_end_matches('/').to_string()
+}
+
+/// What `/etc/os-release` says about this machine.
+#[derive(Debug, Clone)]
+pub struct DetectedSystem {
+    /// `ID`, e.g. `pop` or `endeavouros`
+    pub id: String,
+    /// `PRETTY_NAME`, or `NAME`
+    pub name: Option<String>,
+    /// The distro whose mirrors this system uses: `ID`, or the first known `ID_LIKE`
+    pub family: Distro,
+    pub version: Option<String>,
+    /// Release codename for URL templates; derivatives of Ubuntu use `UBUNTU_CODENAME`
+    pub codename: Option<String>,
+}
+
+impl DetectedSystem {
+    /// The codename to use in URLs for mirrors of `distro`, if it is this system's family.
+    pub fn codename_for(&self, distro: &Distro) -> Option<&str> {
+        (&self.family == distro).then_some(self.codename.as_deref()).flatten()
+    }
+}
+
+/// Parse the contents of an os-release file.
+pub fn parse_os_release(content: &str) -> DetectedSystem {
+    let fields: HashMap<&str, String> = content
+        .lines()
+        .filter(|l| !l.trim_start().starts_with('#'))
+        .filter_map(|l| l.split_once('='))
+        .map(|(k, v)| (k.trim(), v.trim().trim_matches(|c| c == '"' || c == '\'').to_string()))
+        .filter(|(_, v)| !v.is_empty())
+        .collect();
+
+    let id = fields.get("ID").cloned().unwrap_or_default();
+    let family = std::iter::once(id.as_str())
+        .chain(fields.get("ID_LIKE").into_iter().flat_map(|l| l.split_whitespace()))
+        .map(Distro::from_id)
+        .find(|d| *d != Distro::Unknown)
+        .unwrap_or(Distro::Unknown);
+    let codename = match family {
+        Distro::Ubuntu => fields.get("UBUNTU_CODENAME").or(fields.get("VERSION_CODENAME")),
+        _ => fields.get("VERSION_CODENAME"),
+    };
+
+    DetectedSystem {
+        name: fields.get("PRETTY_NAME").or(fields.get("NAME")).cloned(),
+        version: fields.get("VERSION_ID").cloned(),
+        codename: codename.cloned(),
+        family,
+        id,
     }
-    Distro::Unknown
-}
-
-pub async fn test_mirror_speed(url: &str) -> Result<f64> {
-    let client = reqwest::Client::builder()
-        .timeout(Duration::from_secs(10))
-        .build()?;
-
-    let start = std::time::Instant::now();
-    let response = timeout(Duration::from_secs(10), client.get(url).send()).await
-        .context("Request timed out")??;
-    
-    if !response.status().is_success() {
-        return Err(anyhow::anyhow!("Failed to connect: {}", response.status()));
-    }
-
-    let mut total_bytes = 0;
-    let mut stream = response.bytes_stream();
-    
-    // We only download a portion to test speed (e.g. 1MB)
-    let max_bytes = 1024 * 1024; 
-    
-    use futures_util::StreamExt;
-    while let Some(item) = stream.next().await {
-        let chunk = item.context("Failed to read chunk")?;
-        total_bytes += chunk.len();
-        if total_bytes >= max_bytes {
-            break;
+}
+
+pub fn detect_system() -> DetectedSystem {
+    let content = ["/etc/os-release", "/usr/lib/os-release"]
+        .iter()
+        .find_map(|path| fs::read_to_string(path).ok())
+        .unwrap_or_default();
+    parse_os_release(&content)
+}
+
+/// Time the download of `window` of a mirror's test file phase by phase, split
+/// over `streams` concurrent range requests like aria2 or pacman's parallel
+/// downloads. The returned timings are the first stream's, with the total
+/// `bytes` and, for several streams, the aggregate `transfer_mbps`: all bytes
+/// over the wall-clock time from the first request to the last stream's end.
+/// The second value is the per-stream speeds.
+///
+/// When the file is smaller than the window, later segments start past its end
+/// and get HTTP 416; they count as empty rather than failing the mirror.
+pub async fn test_mirror_speed(url: &str, window: ByteWindow, streams: usize) -> Result<(HttpTimings, Vec<f64>)> {
+    let streams = streams.clamp(1, window.len);
+    let segment = window.len / streams;
+    let started = Instant::now();
+    let requests = (0..streams).map(|i| async move {
+        let len = if i + 1 == streams { window.len - segment * i } else { segment };
+        let part = ByteWindow { offset: window.offset + (segment * i) as u64, len };
+        // Hard limit for the speed test portion
+        match http_timing::timed_get(url, part, Duration::from_secs(7)).await {
+            Ok(timings) => Ok(Some((timings, Instant::now()))),
+            Err(e) if i > 0 && e.is::<http_timing::RangeNotSatisfiable>() => Ok(None),
+            Err(e) => Err(e),
         }
-        if start.elapsed().as_secs() > 7 { // Hard limit for speed test portion
-            break;
+    });
+    let results: Vec<_> = futures_util::future::try_join_all(requests).await?.into_iter().flatten().collect();
+
+    let stream_mbps: Vec<f64> = results.iter().map(|(t, _)| t.transfer_mbps).collect();
+    let mut timings = results[0].0.clone();
+    timings.bytes = results.iter().map(|(t, _)| t.bytes).sum();
+    timings.range_ignored = results.iter().any(|(t, _)| t.range_ignored);
+    if streams > 1 {
+        let finished = results.iter().map(|(_, done)| *done).max().unwrap_or(started);
+        let elapsed = finished.duration_since(started).as_secs_f64();
+        timings.transfer_mbps = (timings.bytes as f64 * 8.0) / (elapsed * 1_000_000.0);
+    }
+    Ok((timings, stream_mbps))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Serve a `size`-byte test file with Range support, pausing halfway through every
+    /// body: `pause` for a range at the start, twice that for the next range, and so on.
+    async fn range_server(size: u64, pause: Duration) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/test.bin", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut head = Vec::new();
+                    let mut byte = [0u8; 1];
+                    while !head.ends_with(b"\r\n\r\n") && stream.read(&mut byte).await.unwrap() == 1 {
+                        head.push(byte[0]);
+                    }
+                    let head = String::from_utf8(head).unwrap();
+                    let (start, end) = head.lines()
+                        .find_map(|l| l.strip_prefix("Range: bytes="))
+                        .and_then(|r| r.split_once('-'))
+                        .map(|(a, b)| (a.parse::<u64>().unwrap(), b.parse::<u64>().unwrap()))
+                        .unwrap();
+                    if start >= size {
+                        let _ = stream.write_all(b"HTTP/1.1 416 Range Not Satisfiable\r\nContent-Length: 0\r\n\r\n").await;
+                        return;
+                    }
+                    let end = end.min(size - 1);
+                    let len = (end - start + 1) as usize;
+                    let head = format!(
+                        "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\n\r\n",
+                        start, end, size, len
+                    );
+                    let _ = stream.write_all(head.as_bytes()).await;
+                    let _ = stream.write_all(&vec![0u8; len / 2]).await;
+                    tokio::time::sleep(pause * (1 + start / len as u64) as u32).await;
+                    let _ = stream.write_all(&vec![0u8; len - len / 2]).await;
+                });
+            }
+        });
+        url
+    }
+
+    /// Serve a `size`-byte file as a plain 200 response, ignoring any Range header.
+    async fn no_range_server(size: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/test.bin", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut head = Vec::new();
+                    let mut byte = [0u8; 1];
+                    while !head.ends_with(b"\r\n\r\n") && stream.read(&mut byte).await.unwrap() == 1 {
+                        head.push(byte[0]);
+                    }
+                    let head = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", size);
+                    let _ = stream.write_all(head.as_bytes()).await;
+                    let _ = stream.write_all(&vec![0u8; size]).await;
+                });
+            }
+        });
+        url
+    }
+
+    const POP_OS: &str = r#"NAME="Pop!_OS"
+VERSION="22.04 LTS"
+ID=pop
+ID_LIKE="ubuntu debian"
+PRETTY_NAME="Pop!_OS 22.04 LTS"
+VERSION_ID="22.04"
+VERSION_CODENAME=jammy
+UBUNTU_CODENAME=jammy
+LOGO=distributor-logo-pop-os
+"#;
+
+    const MINT: &str = r#"NAME="Linux Mint"
+VERSION="21.3 (Virginia)"
+ID=linuxmint
+ID_LIKE="ubuntu debian"
+PRETTY_NAME="Linux Mint 21.3"
+VERSION_ID="21.3"
+VERSION_CODENAME=virginia
+UBUNTU_CODENAME=jammy
+"#;
+
+    const ENDEAVOUROS: &str = r#"NAME="EndeavourOS"
+PRETTY_NAME="EndeavourOS"
+ID="endeavouros"
+ID_LIKE="arch"
+BUILD_ID=rolling
+ANSI_COLOR="38;2;23;147;209"
+"#;
+
+    const RHEL: &str = r#"NAME="Red Hat Enterprise Linux"
+VERSION="9.4 (Plow)"
+ID="rhel"
+ID_LIKE="fedora"
+VERSION_ID="9.4"
+PLATFORM_ID="platform:el9"
+PRETTY_NAME="Red Hat Enterprise Linux 9.4 (Plow)"
+"#;
+
+    const CENTOS_STREAM: &str = r#"NAME="CentOS Stream"
+VERSION="9"
+ID="centos"
+ID_LIKE="rhel fedora"
+VERSION_ID="9"
+PRETTY_NAME="CentOS Stream 9"
+"#;
+
+    const ORACLE: &str = r#"NAME="Oracle Linux Server"
+VERSION="8.10"
+ID="ol"
+ID_LIKE="fedora"
+VERSION_ID="8.10"
+PRETTY_NAME="Oracle Linux Server 8.10"
+"#;
+
+    const ALPINE: &str = "NAME=\"Alpine Linux\"\nID=alpine\nVERSION_ID=3.20.3\nPRETTY_NAME=\"Alpine Linux v3.20\"\n";
+
+    #[test]
+    fn os_release_ubuntu_derivatives_use_the_ubuntu_codename() {
+        let pop = parse_os_release(POP_OS);
+        assert_eq!(pop.id, "pop");
+        assert_eq!(pop.family, Distro::Ubuntu);
+        assert_eq!(pop.codename.as_deref(), Some("jammy"));
+        assert_eq!(pop.name.as_deref(), Some("Pop!_OS 22.04 LTS"));
+
+        // Mint has mirrors of its own, named by Mint's codename
+        let mint = parse_os_release(MINT);
+        assert_eq!(mint.family, Distro::Mint);
+        assert_eq!(mint.codename.as_deref(), Some("virginia"));
+        assert_eq!(mint.version.as_deref(), Some("21.3"));
+    }
+
+    #[test]
+    fn os_release_arch_derivative() {
+        let system = parse_os_release(ENDEAVOUROS);
+        assert_eq!(system.id, "endeavouros");
+        assert_eq!(system.family, Distro::Arch);
+        assert_eq!(system.codename, None);
+    }
+
+    #[test]
+    fn os_release_enterprise_linux_uses_rocky_layout() {
+        for (content, id, major) in [(RHEL, "rhel", "9"), (CENTOS_STREAM, "centos", "9"), (ORACLE, "ol", "8")] {
+            let system = parse_os_release(content);
+            assert_eq!(system.id, id);
+            assert_eq!(system.family, Distro::Rocky, "{}", id);
+            assert_eq!(
+                expand_probe("{release}/BaseOS/{arch}/os/images/install.img", &Distro::Rocky, &system),
+                format!("{}/BaseOS/{}/os/images/install.img", major, Distro::Rocky.arch_name())
+            );
+            // Never Fedora paths built from an EL version
+            assert_eq!(
+                expand_probe("releases/{release}/Everything/{arch}/os/images/install.img", &Distro::Fedora, &system),
+                format!(
+                    "releases/{}/Everything/{}/os/images/install.img",
+                    Distro::Fedora.default_release(),
+                    Distro::Fedora.arch_name()
+                )
+            );
         }
     }
 
-    let elapsed = start.elapsed().as_secs_f64();
-    let speed_mbps = (total_bytes as f64 * 8.0) / (elapsed * 1_000_000.0);
-    
-    Ok(speed_mbps)
+    #[test]
+    fn os_release_alpine_branch() {
+        let system = parse_os_release(ALPINE);
+        assert_eq!(system.family, Distro::Alpine);
+        assert_eq!(system.version.as_deref(), Some("3.20.3"));
+        assert_eq!(
+            expand_probe("{release}/main/{arch}/APKINDEX.tar.gz", &Distro::Alpine, &system),
+            format!("v3.20/main/{}/APKINDEX.tar.gz", Distro::Alpine.arch_name())
+        );
+    }
+
+    #[tokio::test]
+    async fn segments_past_the_end_of_a_small_file_count_as_empty() {
+        let url = range_server(100_000, Duration::ZERO).await;
+        let window = ByteWindow { offset: 0, len: 1024 * 1024 };
+
+        let (timings, per_stream) = test_mirror_speed(&url, window, 4).await.unwrap();
+
+        assert_eq!(timings.bytes, 100_000);
+        assert_eq!(per_stream.len(), 1);
+        assert!(!timings.range_ignored);
+    }
+
+    #[tokio::test]
+    async fn servers_ignoring_range_are_flagged_and_capped_to_the_window() {
+        let url = no_range_server(1024 * 1024).await;
+        let window = ByteWindow { offset: 4096, len: 64 * 1024 };
+
+        let (timings, _) = test_mirror_speed(&url, window, 1).await.unwrap();
+
+        assert!(timings.range_ignored);
+        assert_eq!(timings.bytes, 64 * 1024);
+    }
+
+    #[tokio::test]
+    async fn first_segment_past_the_end_still_fails() {
+        let url = range_server(100_000, Duration::ZERO).await;
+        let window = ByteWindow { offset: 200_000, len: 1024 * 1024 };
+
+        let err = test_mirror_speed(&url, window, 4).await.unwrap_err();
+        assert!(err.to_string().contains("smaller than the 200000 byte offset"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn aggregate_speed_is_bytes_over_wall_clock() {
+        let url = range_server(10_000_000, Duration::from_millis(200)).await;
+        let window = ByteWindow { offset: 0, len: 4 * 64 * 1024 };
+
+        let started = Instant::now();
+        let (timings, per_stream) = test_mirror_speed(&url, window, 4).await.unwrap();
+        let outside = (timings.bytes as f64 * 8.0) / (started.elapsed().as_secs_f64() * 1_000_000.0);
+
+        assert_eq!(timings.bytes, 4 * 64 * 1024);
+        assert_eq!(per_stream.len(), 4);
+        // The streams take 200-800ms side by side: the aggregate is what was seen from
+        // outside, well below the sum of the per-stream rates
+        assert!(per_stream.iter().sum::<f64>() > outside * 1.5);
+        assert!(timings.transfer_mbps >= outside && timings.transfer_mbps < outside * 1.2,
+            "aggregate {} vs {} measured outside", timings.transfer_mbps, outside);
+    }
 }
@@ -0,0 +1,343 @@
+use anyhow::{anyhow, Context, Result};
+use futures_util::StreamExt;
+use reqwest::header::{ACCEPT, AUTHORIZATION, WWW_AUTHENTICATE};
+use reqwest::{Client, RequestBuilder, StatusCode};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_IMAGE: &str = "library/alpine:latest";
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_BLOB_BYTES: usize = 8 * 1024 * 1024;
+const MAX_BLOB_SECS: u64 = 7;
+
+const MANIFEST_TYPES: [&str; 4] = [
+    "application/vnd.docker.distribution.manifest.list.v2+json",
+    "application/vnd.oci.image.index.v1+json",
+    "application/vnd.docker.distribution.manifest.v2+json",
+    "application/vnd.oci.image.manifest.v1+json",
+];
+
+/// Timings of one registry pull, measured separately.
+#[derive(Debug, Clone)]
+pub struct RegistryTimings {
+    /// Token handshake; `None` when the registry allows anonymous pulls
+    pub auth: Option<Duration>,
+    /// Fetching the manifest for the image tag
+    pub manifest: Duration,
+    /// Layer blob download throughput
+    pub blob_mbps: f64,
+}
+
+/// Split `library/alpine:latest` (or `alpine`, or `repo@sha256:...`) into
+/// repository and reference. Bare names get Docker Hub's `library/` prefix.
+pub fn parse_image(image: &str) -> (String, String) {
+    let (repo, reference) = if let Some((repo, digest)) = image.split_once('@') {
+        (repo, digest)
+    } else {
+        match image.rsplit_once(':') {
+            Some((repo, tag)) if !tag.contains('/') => (repo, tag),
+            _ => (image, "latest"),
+        }
+    };
+    let repo = if repo.contains('/') { repo.to_string() } else { format!("library/{}", repo) };
+    (repo, reference.to_string())
+}
+
+/// Parse the parameters of a `Bearer realm="...",service="...",scope="..."` challenge.
+fn parse_bearer_challenge(header: &str) -> Option<HashMap<String, String>> {
+    let (scheme, rest) = header.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("bearer") {
+        return None;
+    }
+
+    let mut params = HashMap::new();
+    let mut chars = rest.chars().peekable();
+    loop {
+        while chars.next_if(|c| *c == ',' || c.is_whitespace()).is_some() {}
+        let key: String = std::iter::from_fn(|| chars.next_if(|c| *c != '=')).collect();
+        if key.is_empty() || chars.next().is_none() {
+            break;
+        }
+        let value: String = if chars.next_if_eq(&'"').is_some() {
+            let value = std::iter::from_fn(|| chars.next_if(|c| *c != '"')).collect();
+            chars.next();
+            value
+        } else {
+            std::iter::from_fn(|| chars.next_if(|c| *c != ',')).collect()
+        };
+        params.insert(key.trim().to_lowercase(), value);
+    }
+    params.contains_key("realm").then_some(params)
+}
+
+/// The platform name Docker uses for this machine's architecture.
+fn docker_arch() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "arm" => "arm",
+        other => other,
+    }
+}
+
+struct Registry {
+    client: Client,
+    base: String,
+    token: Option<String>,
+}
+
+impl Registry {
+    fn get(&self, path: &str) -> RequestBuilder {
+        let request = self.client.get(format!("{}/v2/{}", self.base, path));
+        match &self.token {
+            Some(token) => request.header(AUTHORIZATION, format!("Bearer {}", token)),
+            None => request,
+        }
+    }
+
+    /// Ping `/v2/` and run the token handshake if the registry asks for one.
+    async fn authenticate(&mut self, repo: &str) -> Result<Option<Duration>> {
+        let start = Instant::now();
+        let response = self.get("").send().await.context("Registry unreachable")?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            if !response.status().is_success() {
+                return Err(anyhow!("Registry ping failed: {}", response.status()));
+            }
+            return Ok(None);
+        }
+
+        let challenge = response
+            .headers()
+            .get(WWW_AUTHENTICATE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_bearer_challenge)
+            .ok_or_else(|| anyhow!("Registry requires credentials"))?;
+
+        let mut realm = reqwest::Url::parse(&challenge["realm"]).context("Invalid token realm")?;
+        realm.query_pairs_mut().append_pair("scope", &format!("repository:{}:pull", repo));
+        if let Some(service) = challenge.get("service") {
+            realm.query_pairs_mut().append_pair("service", service);
+        }
+        let body = self
+            .client
+            .get(realm)
+            .send()
+            .await
+            .context("Token request failed")?
+            .error_for_status()
+            .context("Token request rejected")?
+            .bytes()
+            .await?;
+        let body: serde_json::Value = serde_json::from_slice(&body).context("Invalid token response")?;
+        let token = body
+            .get("token")
+            .or_else(|| body.get("access_token"))
+            .and_then(|t| t.as_str())
+            .ok_or_else(|| anyhow!("Token response has no token"))?;
+        self.token = Some(token.to_string());
+        Ok(Some(start.elapsed()))
+    }
+
+    async fn manifest(&self, repo: &str, reference: &str) -> Result<serde_json::Value> {
+        let response = self
+            .get(&format!("{}/manifests/{}", repo, reference))
+            .header(ACCEPT, MANIFEST_TYPES.join(", "))
+            .send()
+            .await
+            .context("Manifest request failed")?;
+        if !response.status().is_success() {
+            return Err(anyhow!("Manifest fetch failed: {}", response.status()));
+        }
+        serde_json::from_slice(&response.bytes().await?).context("Invalid manifest")
+    }
+
+    /// Download up to `MAX_BLOB_BYTES` of a blob and return the throughput in Mbps.
+    ///
+    /// The clock starts at the first body chunk, so the request round trip and the
+    /// registry's time to first byte (often a redirect to blob storage) are not
+    /// counted as transfer time.
+    async fn blob_throughput(&self, repo: &str, digest: &str) -> Result<f64> {
+        let requested = Instant::now();
+        let response = self
+            .get(&format!("{}/blobs/{}", repo, digest))
+            .send()
+            .await
+            .context("Blob request failed")?;
+        if !response.status().is_success() {
+            return Err(anyhow!("Blob fetch failed: {}", response.status()));
+        }
+
+        let mut first_chunk: Option<(Instant, usize)> = None;
+        let mut total_bytes = 0;
+        let mut stream = response.bytes_stream();
+        while let Some(item) = stream.next().await {
+            let len = item.context("Failed to read chunk")?.len();
+            let (started, _) = *first_chunk.get_or_insert((Instant::now(), len));
+            total_bytes += len;
+            if total_bytes >= MAX_BLOB_BYTES || started.elapsed().as_secs() >= MAX_BLOB_SECS {
+                break;
+            }
+        }
+
+        // The first chunk arrived at the start of the clock, so only what came after it counts.
+        // A blob that arrived in one chunk has no transfer time; fall back to the whole request.
+        let (bytes, elapsed) = match first_chunk {
+            Some((started, first_len)) if total_bytes > first_len => (total_bytes - first_len, started.elapsed()),
+            _ => (total_bytes, requested.elapsed()),
+        };
+        Ok((bytes as f64 * 8.0) / (elapsed.as_secs_f64() * 1_000_000.0))
+    }
+}
+
+/// Benchmark a registry mirror like a real `docker pull` of `image`: token
+/// handshake, manifest (resolving multi-arch indexes to this platform), then the
+/// largest layer blob for throughput. `base` is the registry root (without `/v2/`).
+pub async fn benchmark_registry(base: &str, image: &str) -> Result<RegistryTimings> {
+    let mut registry = Registry {
+        client: Client::builder().timeout(REQUEST_TIMEOUT).build()?,
+        base: base.trim_end_matches('/').to_string(),
+        token: None,
+    };
+    let (repo, reference) = parse_image(image);
+
+    let auth = registry.authenticate(&repo).await?;
+
+    let start = Instant::now();
+    let mut manifest = registry.manifest(&repo, &reference).await?;
+    let manifest_latency = start.elapsed();
+
+    if let Some(platforms) = manifest.get("manifests").and_then(|m| m.as_array()) {
+        let matches = |m: &&serde_json::Value| {
+            m["platform"]["os"] == "linux" && m["platform"]["architecture"] == docker_arch()
+        };
+        let digest = platforms
+            .iter()
+            .find(matches)
+            .or_else(|| platforms.first())
+            .and_then(|m| m["digest"].as_str())
+            .ok_or_else(|| anyhow!("Image index lists no manifests"))?
+            .to_string();
+        manifest = registry.manifest(&repo, &digest).await?;
+    }
+
+    let layer = manifest
+        .get("layers")
+        .and_then(|l| l.as_array())
+        .and_then(|layers| layers.iter().max_by_key(|l| l["size"].as_u64().unwrap_or(0)))
+        .and_then(|l| l["digest"].as_str())
+        .ok_or_else(|| anyhow!("Manifest has no layers"))?;
+    let blob_mbps = registry.blob_throughput(&repo, layer).await?;
+
+    Ok(RegistryTimings { auth, manifest: manifest_latency, blob_mbps })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    const TOKEN: &str = "stub-token";
+    const CHUNK: usize = 64 * 1024;
+
+    /// Read one request head and return its path and Authorization header.
+    async fn read_request(stream: &mut TcpStream) -> (String, Option<String>) {
+        let mut head = Vec::new();
+        let mut byte = [0u8; 1];
+        while !head.ends_with(b"\r\n\r\n") && stream.read(&mut byte).await.unwrap() == 1 {
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8(head).unwrap();
+        let path = head.split_whitespace().nth(1).unwrap_or("").to_string();
+        let auth = head.lines()
+            .find_map(|l| l.split_once(':').filter(|(k, _)| k.eq_ignore_ascii_case("authorization")))
+            .map(|(_, v)| v.trim().to_string());
+        (path, auth)
+    }
+
+    async fn respond(stream: &mut TcpStream, status: &str, headers: &str, body: &[u8]) {
+        let head = format!("HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n", status, headers, body.len());
+        stream.write_all(head.as_bytes()).await.unwrap();
+        stream.write_all(body).await.unwrap();
+    }
+
+    /// A registry that wants a token, serves a multi-arch index for `library/alpine:latest`,
+    /// and sends the big layer slowly: 300ms before the first byte, then two chunks 100ms apart.
+    /// Returns its base URL and the (path, authorization) of every request.
+    async fn stub_registry() -> (String, Arc<Mutex<Vec<(String, Option<String>)>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let (realm, log) = (format!("{}/token", base), Arc::clone(&requests));
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let (path, auth) = read_request(&mut stream).await;
+                log.lock().unwrap().push((path.clone(), auth.clone()));
+                let authorized = auth.as_deref() == Some(&format!("Bearer {}", TOKEN));
+
+                match path.as_str() {
+                    p if p.starts_with("/token?") => {
+                        respond(&mut stream, "200 OK", "", format!(r#"{{"token":"{}"}}"#, TOKEN).as_bytes()).await;
+                    }
+                    _ if !authorized => {
+                        let challenge = format!("WWW-Authenticate: Bearer realm=\"{}\",service=\"stub\"\r\n", realm);
+                        respond(&mut stream, "401 Unauthorized", &challenge, b"").await;
+                    }
+                    "/v2/" => respond(&mut stream, "200 OK", "", b"{}").await,
+                    "/v2/library/alpine/manifests/latest" => {
+                        let index = serde_json::json!({"manifests": [
+                            {"digest": "sha256:other", "platform": {"os": "linux", "architecture": "s390x-stub"}},
+                            {"digest": "sha256:mine", "platform": {"os": "linux", "architecture": docker_arch()}},
+                        ]});
+                        respond(&mut stream, "200 OK", "", index.to_string().as_bytes()).await;
+                    }
+                    "/v2/library/alpine/manifests/sha256:mine" => {
+                        let manifest = serde_json::json!({"layers": [
+                            {"digest": "sha256:small", "size": 10},
+                            {"digest": "sha256:big", "size": 2 * CHUNK},
+                        ]});
+                        respond(&mut stream, "200 OK", "", manifest.to_string().as_bytes()).await;
+                    }
+                    "/v2/library/alpine/blobs/sha256:big" => {
+                        tokio::time::sleep(Duration::from_millis(300)).await;
+                        let head = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", 2 * CHUNK);
+                        stream.write_all(head.as_bytes()).await.unwrap();
+                        stream.write_all(&[0u8; CHUNK]).await.unwrap();
+                        stream.flush().await.unwrap();
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        stream.write_all(&[0u8; CHUNK]).await.unwrap();
+                    }
+                    _ => respond(&mut stream, "404 Not Found", "", b"").await,
+                }
+            }
+        });
+        (base, requests)
+    }
+
+    #[tokio::test]
+    async fn pulls_token_manifest_and_largest_blob() {
+        let (base, requests) = stub_registry().await;
+
+        let timings = benchmark_registry(&base, "alpine").await.unwrap();
+
+        assert!(timings.auth.is_some());
+        let requests = requests.lock().unwrap().clone();
+        let paths: Vec<&str> = requests.iter().map(|(p, _)| p.as_str()).collect();
+        assert_eq!(paths[0], "/v2/");
+        assert!(paths[1].starts_with("/token?scope=repository%3Alibrary%2Falpine%3Apull&service=stub"), "{}", paths[1]);
+        assert_eq!(&paths[2..], [
+            "/v2/library/alpine/manifests/latest",
+            "/v2/library/alpine/manifests/sha256:mine",
+            "/v2/library/alpine/blobs/sha256:big",
+        ]);
+        let bearer = format!("Bearer {}", TOKEN);
+        assert!(requests[2..].iter().all(|(_, auth)| auth.as_deref() == Some(bearer.as_str())));
+
+        // After the first read, 64-128 KiB arrive in ~100ms (5-10 Mbps); counting the
+        // 300ms wait before the first byte would give ~2.6 Mbps
+        assert!(timings.blob_mbps > 3.5, "{} Mbps", timings.blob_mbps);
+    }
+}
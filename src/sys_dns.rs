@@ -1,41 +1,266 @@
 use anyhow::{Result, Context, anyhow};
+use std::collections::HashMap;
+use std::fs;
 use std::net::IpAddr;
 use std::process::Command;
+#[cfg(target_os = "linux")]
+use std::path::{Path, PathBuf};
+
+const INTERFACES_FILE: &str = "interfaces.json";
+
+/// A network interface (or NetworkManager connection / macOS network service)
+/// that DNS settings can be applied to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetTarget {
+    pub name: String,
+    pub description: String,
+}
 
 /// Set the system DNS to the specified IP address.
 /// Supports Linux, Windows, and macOS.
-pub fn set_system_dns(dns_ip: IpAddr) -> Result<()> {
+///
+/// `target` names the interface/connection to change (see [`list_targets`]).
+/// When `None`, the primary one is picked automatically.
+pub fn set_system_dns(dns_ip: IpAddr, target: Option<&str>) -> Result<()> {
     #[cfg(target_os = "linux")]
     {
-        set_linux_dns(dns_ip)
+        set_linux_dns(dns_ip, target)
     }
     #[cfg(target_os = "windows")]
     {
-        set_windows_dns(dns_ip)
+        set_windows_dns(dns_ip, target)
     }
     #[cfg(target_os = "macos")]
     {
-        set_macos_dns(dns_ip)
+        set_macos_dns(dns_ip, target)
     }
     #[cfg(not(any(target_os = "linux", target_os = "windows", target_os = "macos")))]
     {
+        let _ = (dns_ip, target);
         Err(anyhow!("System DNS configuration is not supported on this operating system."))
     }
 }
 
+/// List the interfaces/connections DNS can be applied to, using the same
+/// backend `set_system_dns` would use.
+pub fn list_targets() -> Vec<NetTarget> {
+    #[cfg(target_os = "linux")]
+    {
+        list_linux_targets()
+    }
+    #[cfg(target_os = "windows")]
+    {
+        list_windows_targets()
+    }
+    #[cfg(target_os = "macos")]
+    {
+        list_macos_targets()
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "windows", target_os = "macos")))]
+    {
+        Vec::new()
+    }
+}
+
+/// Identify the network we are currently on: its default gateway plus, since many
+/// networks share a gateway address like 192.168.1.1, the NetworkManager connection
+/// (one per Wi-Fi SSID) and the gateway's MAC address when they are known.
+pub fn current_network_id() -> Option<String> {
+    #[cfg(target_os = "linux")]
+    {
+        let output = Command::new("ip").args(["route", "show", "default"]).output().ok()?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let words = stdout.split_whitespace().collect::<Vec<_>>();
+        let via = words.iter().position(|&s| s == "via")?;
+        let gateway = words.get(via + 1)?.to_string();
+        let device = words.iter().position(|&s| s == "dev").and_then(|i| words.get(i + 1));
 
+        let connection = device.and_then(|dev| nm_active_connection_uuid(dev));
+        let mac = Command::new("ip")
+            .args(["neigh", "show", &gateway])
+            .output()
+            .ok()
+            .and_then(|o| find_mac(&String::from_utf8_lossy(&o.stdout)));
+        Some(network_key(gateway, [connection, mac]))
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let output = Command::new("route").args(["-n", "get", "default"]).output().ok()?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let gateway = stdout.lines()
+            .find_map(|l| l.trim().strip_prefix("gateway:"))
+            .map(|gw| gw.trim().to_string())?;
+        let mac = Command::new("arp")
+            .args(["-n", &gateway])
+            .output()
+            .ok()
+            .and_then(|o| find_mac(&String::from_utf8_lossy(&o.stdout)));
+        Some(network_key(gateway, [mac]))
+    }
+    #[cfg(target_os = "windows")]
+    {
+        let output = Command::new("powershell")
+            .args(["-Command", "(Get-NetRoute -DestinationPrefix '0.0.0.0/0' | Select-Object -First 1).NextHop"])
+            .output()
+            .ok()?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let gateway = stdout.lines().next().map(|gw| gw.trim().to_string()).filter(|gw| !gw.is_empty())?;
+        let mac = Command::new("powershell")
+            .args(["-Command", &format!("(Get-NetNeighbor -IPAddress '{}' | Select-Object -First 1).LinkLayerAddress", gateway)])
+            .output()
+            .ok()
+            .and_then(|o| find_mac(&String::from_utf8_lossy(&o.stdout)));
+        Some(network_key(gateway, [mac]))
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "windows", target_os = "macos")))]
+    {
+        None
+    }
+}
+
+/// `gateway`, followed by whichever of the extra identifiers are known.
+#[cfg(any(target_os = "linux", target_os = "windows", target_os = "macos"))]
+fn network_key<const N: usize>(gateway: String, extra: [Option<String>; N]) -> String {
+    extra.into_iter().flatten().fold(gateway, |key, part| format!("{} {}", key, part))
+}
+
+/// The first MAC address in command output (`aa:bb:cc:dd:ee:ff` or `AA-BB-...`), lowercased.
+#[cfg(any(target_os = "linux", target_os = "windows", target_os = "macos"))]
+fn find_mac(output: &str) -> Option<String> {
+    output
+        .split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+        .find(|word| {
+            let groups: Vec<&str> = word.split([':', '-']).collect();
+            groups.len() == 6 && groups.iter().all(|g| g.len() == 2 && g.chars().all(|c| c.is_ascii_hexdigit()))
+        })
+        .map(|mac| mac.replace('-', ":").to_lowercase())
+}
+
+/// UUID of the NetworkManager connection active on `device`. Wi-Fi connections are
+/// per SSID, so this tells apart networks that hand out the same gateway address.
+#[cfg(target_os = "linux")]
+fn nm_active_connection_uuid(device: &str) -> Option<String> {
+    let output = Command::new("nmcli")
+        .args(["-t", "-f", "UUID,DEVICE", "connection", "show", "--active"])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(_, dev)| *dev == device)
+        .map(|(uuid, _)| uuid.to_string())
+}
+
+/// Send queries for `domain` and its subdomains to `server`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DnsRoute {
+    pub domain: String,
+    pub server: IpAddr,
+    /// Link the server is reachable on (e.g. a VPN's `tun0`); `None` = the default link
+    pub link: Option<String>,
+}
+
+impl DnsRoute {
+    /// Parse an editor entry of the form `domain` or `domain@link`.
+    pub fn parse(entry: &str, server: IpAddr) -> Result<Self> {
+        let (domain, link) = match entry.trim().split_once('@') {
+            Some((domain, link)) => (domain, Some(link.trim().to_string())),
+            None => (entry.trim(), None),
+        };
+        let domain = domain.trim().trim_start_matches("~").trim_matches('.').to_lowercase();
+        if domain.is_empty() || domain.contains(char::is_whitespace) {
+            return Err(anyhow!("Invalid domain suffix: {}", entry));
+        }
+        if link.as_deref() == Some("") {
+            return Err(anyhow!("Missing link name after '@' in: {}", entry));
+        }
+        Ok(Self { domain, server, link })
+    }
+}
+
+/// Split DNS: per-domain routes, plus the resolver for everything else.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DnsRouting {
+    pub default: Option<IpAddr>,
+    pub routes: Vec<DnsRoute>,
+}
+
+/// Apply a split DNS routing table via systemd-resolved.
+///
+/// systemd-resolved routes by link: every link gets its servers and `~domain`
+/// routing domains, and the default link also takes `~.` for everything else.
+/// Since a link only talks to one resolver at a time, each link may carry only
+/// one distinct server; routes to another resolver need another link (`domain@link`).
+pub fn apply_split_dns(routing: &DnsRouting) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        apply_split_dns_resolvectl(routing)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = routing;
+        Err(anyhow!("Split DNS requires systemd-resolved (Linux)."))
+    }
+}
+
+fn load_interface_choices() -> HashMap<String, String> {
+    crate::file_loader::config_dir()
+        .and_then(|dir| fs::read_to_string(dir.join(INTERFACES_FILE)).ok())
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+/// The interface previously chosen on the current network, if any.
+pub fn remembered_target() -> Option<String> {
+    let network = current_network_id()?;
+    load_interface_choices().remove(&network)
+}
+
+/// Remember `target` as the interface to use on the current network.
+pub fn remember_target(target: &str) -> Result<()> {
+    let network = current_network_id().context("Could not identify the current network")?;
+    let dir = crate::file_loader::config_dir().context("Could not determine the config directory")?;
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    let mut choices = load_interface_choices();
+    choices.insert(network, target.to_string());
+    let path = dir.join(INTERFACES_FILE);
+    fs::write(&path, serde_json::to_string_pretty(&choices)?)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(())
+}
 
 #[cfg(target_os = "windows")]
-fn set_windows_dns(dns_ip: IpAddr) -> Result<()> {
-    // 1. Find the primary interface name (active and connected)
-    // We use powershell to get the interface name because it's more reliable than netsh parsing
-    let output = Command::new("powershell")
-        .args(["-Command", "Get-NetAdapter | Where-Object { $_.Status -eq 'Up' } | Select-Object -ExpandProperty Name"])
+fn list_windows_targets() -> Vec<NetTarget> {
+    // We use powershell to get the interface names because it's more reliable than netsh parsing
+    let Ok(output) = Command::new("powershell")
+        .args(["-Command", "Get-NetAdapter | Where-Object { $_.Status -eq 'Up' } | ForEach-Object { $_.Name + '|' + $_.InterfaceDescription }"])
         .output()
-        .context("Failed to run powershell to get network adapters")?;
-    
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let interface = stdout.lines().next().context("No active network adapters found")?.trim();
+    else {
+        return Vec::new();
+    };
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let (name, desc) = line.trim().split_once('|')?;
+            Some(NetTarget { name: name.to_string(), description: desc.to_string() })
+        })
+        .collect()
+}
+
+#[cfg(target_os = "windows")]
+fn set_windows_dns(dns_ip: IpAddr, target: Option<&str>) -> Result<()> {
+    // 1. Use the chosen interface, or the primary one (active and connected)
+    let interface = match target {
+        Some(name) => name.to_string(),
+        None => list_windows_targets()
+            .into_iter()
+            .next()
+            .context("No active network adapters found")?
+            .name,
+    };
 
     // 2. Set DNS via netsh (Requires Administrator)
     let status = Command::new("netsh")
@@ -51,33 +276,47 @@ fn set_windows_dns(dns_ip: IpAddr) -> Result<()> {
 }
 
 #[cfg(target_os = "macos")]
-fn set_macos_dns(dns_ip: IpAddr) -> Result<()> {
-    // 1. Get the primary network service
-    let output = Command::new("networksetup")
+fn list_macos_targets() -> Vec<NetTarget> {
+    let Ok(output) = Command::new("networksetup")
         .arg("-listallnetworkservices")
         .output()
-        .context("Failed to list network services")?;
-    
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    // Usually the first one that isn't a header is the active one, 
-    // but we can try to find one with an IP
-    let mut service = "Wi-Fi"; // Default 
-    for s in stdout.lines().skip(1) {
-        if s.starts_with('*') { continue; } // Skip disabled
-        let info = Command::new("networksetup")
-            .args(["-getinfo", s])
-            .output();
-        if let Ok(i) = info {
-            if String::from_utf8_lossy(&i.stdout).contains("IP address:") {
-                service = s;
-                break;
+    else {
+        return Vec::new();
+    };
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .skip(1) // Header
+        .filter(|s| !s.starts_with('*')) // Skip disabled
+        .map(|s| {
+            let has_ip = Command::new("networksetup")
+                .args(["-getinfo", s])
+                .output()
+                .map(|i| String::from_utf8_lossy(&i.stdout).contains("IP address:"))
+                .unwrap_or(false);
+            NetTarget {
+                name: s.to_string(),
+                description: if has_ip { "connected".to_string() } else { "no IP address".to_string() },
             }
-        }
-    }
+        })
+        .collect()
+}
+
+#[cfg(target_os = "macos")]
+fn set_macos_dns(dns_ip: IpAddr, target: Option<&str>) -> Result<()> {
+    // 1. Use the chosen network service, or the first one with an IP
+    let service = match target {
+        Some(name) => name.to_string(),
+        None => list_macos_targets()
+            .into_iter()
+            .find(|t| t.description == "connected")
+            .map(|t| t.name)
+            .unwrap_or_else(|| "Wi-Fi".to_string()), // Default
+    };
 
     // 2. Set DNS (Requires sudo)
     let status = Command::new("sudo")
-        .args(["networksetup", "-setdnsservers", service, &dns_ip.to_string()])
+        .args(["networksetup", "-setdnsservers", &service, &dns_ip.to_string()])
         .status()
         .context("Failed to run sudo networksetup")?;
 
@@ -89,10 +328,71 @@ fn set_macos_dns(dns_ip: IpAddr) -> Result<()> {
 }
 
 #[cfg(target_os = "linux")]
-fn set_linux_dns(dns_ip: IpAddr) -> Result<()> {
+fn list_linux_targets() -> Vec<NetTarget> {
+    // Same backend order as set_linux_dns: NetworkManager connections, then links
+    if let Ok(output) = Command::new("nmcli")
+        .args(["-t", "-f", "NAME,TYPE,DEVICE", "connection", "show", "--active"])
+        .output()
+        && output.status.success()
+    {
+        return String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| !line.contains("loopback"))
+            .filter_map(|line| {
+                let mut fields = line.split(':');
+                let name = fields.next()?;
+                let kind = fields.next().unwrap_or("");
+                let device = fields.next().unwrap_or("");
+                Some(NetTarget {
+                    name: name.to_string(),
+                    description: format!("{} on {}", kind, device),
+                })
+            })
+            .collect();
+    }
+
+    // Without resolvectl, DNS goes into the system-wide resolv.conf: there is nothing to pick
+    if Command::new("resolvectl").arg("--version").output().is_err() {
+        return Vec::new();
+    }
+
+    let default_dev = default_route_device().ok();
+    let Ok(output) = Command::new("ip").args(["-o", "link", "show", "up"]).output() else {
+        return Vec::new();
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            // "2: wlan0: <BROADCAST,...> ..." (veth names look like "veth0@if5")
+            let name = line.split(": ").nth(1)?.split('@').next()?;
+            if name == "lo" {
+                return None;
+            }
+            let description = if default_dev.as_deref() == Some(name) { "default route" } else { "link" };
+            Some(NetTarget { name: name.to_string(), description: description.to_string() })
+        })
+        .collect()
+}
+
+#[cfg(target_os = "linux")]
+fn default_route_device() -> Result<String> {
+    let output = Command::new("ip")
+        .args(["route", "show", "default"])
+        .output()
+        .context("Failed to run ip route show default")?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let dev = stdout.split_whitespace().collect::<Vec<_>>();
+    let dev_index = dev.iter().position(|&s| s == "dev").context("Could not find 'dev' in default route")?;
+    let interface = dev.get(dev_index + 1).context("Could not find interface name after 'dev'")?;
+    Ok(interface.to_string())
+}
+
+#[cfg(target_os = "linux")]
+fn set_linux_dns(dns_ip: IpAddr, target: Option<&str>) -> Result<()> {
     // Try nmcli first (NetworkManager)
-    if let Ok(_) = Command::new("nmcli").arg("--version").output() {
-        match set_via_nmcli(dns_ip) {
+    if Command::new("nmcli").arg("--version").output().is_ok() {
+        match set_via_nmcli(dns_ip, target) {
             Ok(_) => return Ok(()),
             Err(e) => {
                 // If it fails (maybe not using NM), fall back to resolvectl
@@ -102,31 +402,42 @@ fn set_linux_dns(dns_ip: IpAddr) -> Result<()> {
     }
     
     // Try resolvectl (systemd-resolved)
-    if let Ok(_) = Command::new("resolvectl").arg("--version").output() {
-        return set_via_resolvectl(dns_ip);
+    if Command::new("resolvectl").arg("--version").output().is_ok() {
+        return set_via_resolvectl(dns_ip, target);
     }
 
-    Err(anyhow!("Could not find nmcli or resolvectl to configure DNS. Please ensure you have NetworkManager or systemd-resolved installed."))
+    // Minimal systems (containers, Alpine, plain Debian) have neither: edit resolv.conf directly.
+    // That file is system-wide, so it cannot honour a per-interface choice.
+    if let Some(name) = target {
+        return Err(anyhow!(
+            "Cannot set DNS on {} only: neither nmcli nor resolvectl is available, and /etc/resolv.conf applies to every interface. Run without --interface.",
+            name
+        ));
+    }
+    set_via_resolv_conf(dns_ip)
 }
 
 #[cfg(target_os = "linux")]
-fn set_via_nmcli(dns_ip: IpAddr) -> Result<()> {
-    // 1. Get active connection Name
-    let output = Command::new("nmcli")
-        .args(["-t", "-f", "NAME,TYPE", "connection", "show", "--active"])
-        .output()
-        .context("Failed to run nmcli show active")?;
-    
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    
-    // Find the first non-loopback connection (usually ethernet or wifi)
-    let conn_name = stdout.lines()
-        .filter(|line| !line.contains("loopback"))
-        .next()
-        .context("No active network connections found via nmcli")?
-        .split(':')
-        .next()
-        .context("Failed to parse connection name")?;
+fn set_via_nmcli(dns_ip: IpAddr, target: Option<&str>) -> Result<()> {
+    // 1. Use the chosen connection, or the first non-loopback active one (usually ethernet or wifi)
+    let conn_name = match target {
+        Some(name) => name.to_string(),
+        None => {
+            let output = Command::new("nmcli")
+                .args(["-t", "-f", "NAME,TYPE", "connection", "show", "--active"])
+                .output()
+                .context("Failed to run nmcli show active")?;
+
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            stdout.lines().find(|line| !line.contains("loopback"))
+                .context("No active network connections found via nmcli")?
+                .split(':')
+                .next()
+                .context("Failed to parse connection name")?
+                .to_string()
+        }
+    };
+    let conn_name = conn_name.as_str();
 
     // 2. Set DNS (Using sudo - user must have sudo access or run as root)
     // Note: In TUI, sudo might prompt for password.
@@ -153,21 +464,17 @@ fn set_via_nmcli(dns_ip: IpAddr) -> Result<()> {
 }
 
 #[cfg(target_os = "linux")]
-fn set_via_resolvectl(dns_ip: IpAddr) -> Result<()> {
-    // Find default interface
-    let output = Command::new("ip")
-        .args(["route", "show", "default"])
-        .output()
-        .context("Failed to run ip route show default")?;
-    
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let dev = stdout.split_whitespace().collect::<Vec<_>>();
-    let dev_index = dev.iter().position(|&s| s == "dev").context("Could not find 'dev' in default route")?;
-    let interface = dev.get(dev_index + 1).context("Could not find interface name after 'dev'")?;
+fn set_via_resolvectl(dns_ip: IpAddr, target: Option<&str>) -> Result<()> {
+    // Use the chosen link, or the default-route one. The target may be a NetworkManager
+    // connection name (when nmcli failed above), which resolvectl only knows by its device.
+    let interface = match target {
+        Some(name) => nm_connection_device(name).unwrap_or_else(|| name.to_string()),
+        None => default_route_device()?,
+    };
 
     // Set DNS via resolvectl
     let status = Command::new("sudo")
-        .args(["resolvectl", "dns", interface, &dns_ip.to_string()])
+        .args(["resolvectl", "dns", &interface, &dns_ip.to_string()])
         .status()
         .context("Failed to run sudo resolvectl dns")?;
 
@@ -182,3 +489,269 @@ fn set_via_resolvectl(dns_ip: IpAddr) -> Result<()> {
 
     Ok(())
 }
+
+/// The device a NetworkManager connection is bound to, e.g. "Wired connection 1" -> "enp3s0".
+#[cfg(target_os = "linux")]
+fn nm_connection_device(conn_name: &str) -> Option<String> {
+    let output = Command::new("nmcli")
+        .args(["-g", "GENERAL.DEVICES", "connection", "show", conn_name])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())?;
+    String::from_utf8_lossy(&output.stdout)
+        .split([',', '\n'])
+        .map(str::trim)
+        .find(|dev| !dev.is_empty())
+        .map(str::to_string)
+}
+
+#[cfg(target_os = "linux")]
+fn apply_split_dns_resolvectl(routing: &DnsRouting) -> Result<()> {
+    if Command::new("resolvectl").arg("--version").output().is_err() {
+        return Err(anyhow!("resolvectl not found. Split DNS requires systemd-resolved."));
+    }
+    let default_link = default_route_device()?;
+
+    // (link, servers, routing domains), in first-seen order
+    let mut links: Vec<(String, Vec<IpAddr>, Vec<String>)> = Vec::new();
+    fn link_index(links: &mut Vec<(String, Vec<IpAddr>, Vec<String>)>, link: &str) -> usize {
+        links.iter().position(|(l, _, _)| l == link).unwrap_or_else(|| {
+            links.push((link.to_string(), Vec::new(), Vec::new()));
+            links.len() - 1
+        })
+    }
+
+    if let Some(default) = routing.default {
+        let i = link_index(&mut links, &default_link);
+        links[i].1.push(default);
+        links[i].2.push("~.".to_string());
+    }
+    for route in &routing.routes {
+        let i = link_index(&mut links, route.link.as_deref().unwrap_or(&default_link));
+        if !links[i].1.contains(&route.server) {
+            links[i].1.push(route.server);
+        }
+        links[i].2.push(format!("~{}", route.domain));
+    }
+
+    if let Some((link, servers, _)) = links.iter().find(|(_, servers, _)| servers.len() > 1) {
+        let list = servers.iter().map(|s| s.to_string()).collect::<Vec<_>>().join(", ");
+        return Err(anyhow!(
+            "Link {} would need several resolvers ({}). Route the extra domains via another link with domain@link.",
+            link, list
+        ));
+    }
+
+    for (link, servers, domains) in &links {
+        let mut args = vec!["resolvectl".to_string(), "dns".to_string(), link.clone()];
+        args.extend(servers.iter().map(|s| s.to_string()));
+        let status = Command::new("sudo")
+            .args(&args)
+            .status()
+            .context("Failed to run sudo resolvectl dns")?;
+        if !status.success() {
+            return Err(anyhow!("sudo resolvectl dns {} failed. Ensure you have sudo privileges.", link));
+        }
+
+        let mut args = vec!["resolvectl".to_string(), "domain".to_string(), link.clone()];
+        args.extend(domains.iter().cloned());
+        let status = Command::new("sudo")
+            .args(&args)
+            .status()
+            .context("Failed to run sudo resolvectl domain")?;
+        if !status.success() {
+            return Err(anyhow!("sudo resolvectl domain {} failed.", link));
+        }
+    }
+
+    // Flush caches
+    let _ = Command::new("sudo")
+        .args(["resolvectl", "flush-caches"])
+        .status();
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn set_via_resolv_conf(dns_ip: IpAddr) -> Result<()> {
+    write_resolv_conf(Path::new("/"), &[dns_ip])
+        .context("Could not find nmcli or resolvectl, and rewriting /etc/resolv.conf failed")?;
+    Ok(())
+}
+
+/// Rewrite `<root>/etc/resolv.conf` so that `servers` are its only nameservers.
+///
+/// `search`, `domain`, `options` and comment lines are kept as they are. The first
+/// time, the original file is copied to `resolv.conf.dns-master.bak`; later runs
+/// leave that copy alone so it stays the pre-DNS Master file. The new one is written to a
+/// temporary file and renamed into place, so readers never see a half-written file.
+/// Refuses to touch a symlink, since that means another service owns the file.
+/// Returns the path of the backup, if there was a file to back up.
+#[cfg(target_os = "linux")]
+pub fn write_resolv_conf(root: &Path, servers: &[IpAddr]) -> Result<Option<PathBuf>> {
+    let path = root.join("etc/resolv.conf");
+
+    if let Ok(meta) = fs::symlink_metadata(&path)
+        && meta.file_type().is_symlink()
+    {
+        let target = fs::read_link(&path).unwrap_or_default();
+        let target_str = target.to_string_lossy();
+        let owner = if target_str.contains("systemd/resolve") {
+            "systemd-resolved"
+        } else if target_str.contains("NetworkManager") {
+            "NetworkManager"
+        } else if target_str.contains("resolvconf") {
+            "resolvconf"
+        } else {
+            "another service"
+        };
+        return Err(anyhow!(
+            "{} is a symlink to {} (managed by {}). Refusing to overwrite it.",
+            path.display(), target.display(), owner
+        ));
+    }
+
+    let existing = match fs::read_to_string(&path) {
+        Ok(content) => Some(content),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+
+    let backup = path.with_file_name("resolv.conf.dns-master.bak");
+    let backup = match &existing {
+        Some(_) if backup.exists() => Some(backup),
+        Some(_) => {
+            fs::copy(&path, &backup)
+                .with_context(|| format!("Failed to back up {} (are you root?)", path.display()))?;
+            Some(backup)
+        }
+        None => None,
+    };
+
+    let content = render_resolv_conf(existing.as_deref().unwrap_or(""), servers);
+    crate::fs_utils::write_atomic(&path, &content)?;
+
+    Ok(backup)
+}
+
+/// Build a new resolv.conf from `existing`, replacing every `nameserver` line with `servers`.
+#[cfg(target_os = "linux")]
+fn render_resolv_conf(existing: &str, servers: &[IpAddr]) -> String {
+    const HEADER: &str = "# Nameservers set by DNS Master";
+
+    let mut out = String::new();
+    out.push_str(HEADER);
+    out.push('\n');
+    for server in servers {
+        out.push_str(&format!("nameserver {}\n", server));
+    }
+    for line in existing.lines() {
+        let trimmed = line.trim_start();
+        if trimmed == HEADER || trimmed.split_whitespace().next() == Some("nameserver") {
+            continue;
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::fs_utils::TempDir;
+
+    #[test]
+    fn resolv_conf_keeps_search_and_options() {
+        let root = TempDir::new("resolv-keep");
+        let path = root.write(
+            "etc/resolv.conf",
+            "# generated by dhcp\nsearch lan example.com\nnameserver 192.168.1.1\noptions edns0 trust-ad\nnameserver 192.168.1.2\n",
+        );
+
+        write_resolv_conf(root.path(), &["1.1.1.1".parse().unwrap(), "9.9.9.9".parse().unwrap()]).unwrap();
+
+        let written = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            written,
+            "# Nameservers set by DNS Master\nnameserver 1.1.1.1\nnameserver 9.9.9.9\n\
+             # generated by dhcp\nsearch lan example.com\noptions edns0 trust-ad\n"
+        );
+
+        // Applying again replaces our own header and servers rather than stacking them
+        write_resolv_conf(root.path(), &["8.8.8.8".parse().unwrap()]).unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        assert_eq!(written.matches("# Nameservers set by DNS Master").count(), 1);
+        assert_eq!(written.matches("nameserver").count(), 1);
+        assert!(written.contains("nameserver 8.8.8.8\n"));
+    }
+
+    #[test]
+    fn resolv_conf_refuses_symlink() {
+        let root = TempDir::new("resolv-link");
+        let stub = root.write("run/systemd/resolve/stub-resolv.conf", "nameserver 127.0.0.53\n");
+        fs::create_dir_all(root.path().join("etc")).unwrap();
+        std::os::unix::fs::symlink(&stub, root.path().join("etc/resolv.conf")).unwrap();
+
+        let err = write_resolv_conf(root.path(), &["1.1.1.1".parse().unwrap()]).unwrap_err();
+        assert!(err.to_string().contains("systemd-resolved"), "{}", err);
+        assert_eq!(fs::read_to_string(&stub).unwrap(), "nameserver 127.0.0.53\n");
+        assert!(!root.path().join("etc/resolv.conf.dns-master.bak").exists());
+    }
+
+    #[test]
+    fn resolv_conf_writes_backup() {
+        let root = TempDir::new("resolv-backup");
+        let original = "search lan\nnameserver 192.168.1.1\n";
+        root.write("etc/resolv.conf", original);
+
+        let backup = write_resolv_conf(root.path(), &["1.1.1.1".parse().unwrap()]).unwrap();
+
+        let backup = backup.expect("existing file should be backed up");
+        assert_eq!(backup, root.path().join("etc/resolv.conf.dns-master.bak"));
+        assert_eq!(fs::read_to_string(&backup).unwrap(), original);
+    }
+
+    #[test]
+    fn resolv_conf_backup_survives_second_apply() {
+        let root = TempDir::new("resolv-twice");
+        let original = "search lan\nnameserver 192.168.1.1\n";
+        root.write("etc/resolv.conf", original);
+
+        write_resolv_conf(root.path(), &["1.1.1.1".parse().unwrap()]).unwrap();
+        let backup = write_resolv_conf(root.path(), &["9.9.9.9".parse().unwrap()]).unwrap().unwrap();
+
+        assert_eq!(fs::read_to_string(&backup).unwrap(), original);
+        assert!(fs::read_to_string(root.path().join("etc/resolv.conf")).unwrap().contains("nameserver 9.9.9.9\n"));
+    }
+
+    #[test]
+    fn network_id_includes_gateway_mac() {
+        let neigh = "192.168.1.1 dev wlp2s0 lladdr 3C:84:6A:0B:12:EF REACHABLE\n";
+        assert_eq!(find_mac(neigh).as_deref(), Some("3c:84:6a:0b:12:ef"));
+        assert_eq!(find_mac("? (192.168.1.1) at 3c:84:6a:b:12:ef on en0 ifscope [ethernet]"), None);
+        assert_eq!(find_mac("3C-84-6A-0B-12-EF").as_deref(), Some("3c:84:6a:0b:12:ef"));
+        assert_eq!(find_mac("192.168.1.1 dev eth0 FAILED"), None);
+
+        let uuid = Some("5b3c0f6e-0d2e-4c55-9a55-0c1f6a0a8f11".to_string());
+        assert_eq!(
+            network_key("192.168.1.1".into(), [uuid, find_mac(neigh)]),
+            "192.168.1.1 5b3c0f6e-0d2e-4c55-9a55-0c1f6a0a8f11 3c:84:6a:0b:12:ef"
+        );
+        assert_eq!(network_key("192.168.1.1".into(), [None, None]), "192.168.1.1");
+    }
+
+    #[test]
+    fn resolv_conf_created_without_backup() {
+        let root = TempDir::new("resolv-new");
+        fs::create_dir_all(root.path().join("etc")).unwrap();
+
+        let backup = write_resolv_conf(root.path(), &["1.1.1.1".parse().unwrap()]).unwrap();
+
+        assert!(backup.is_none());
+        assert_eq!(
+            fs::read_to_string(root.path().join("etc/resolv.conf")).unwrap(),
+            "# Nameservers set by DNS Master\nnameserver 1.1.1.1\n"
+        );
+    }
+}
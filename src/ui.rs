@@ -1,10 +1,12 @@
-use crate::app::{App, AppMode, AppState, SortColumn};
-use crate::dns_utils::DnsTestResult;
+use crate::app::{App, AppMode, AppState, MirrorField, SortColumn};
+use crate::mirror_apply::DiffLine;
+use crate::mirror_freshness::format_lag;
+use crate::mirror_utils::{Distro, MirrorOrigin, MirrorTestResult};
 use ratatui::{
-    layout::{Constraint, Direction, Layout},
+    layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Line, Span},
-    widgets::{BarChart, Block, Borders, Cell, Gauge, List, ListItem, Paragraph, Row, Table},
+    widgets::{BarChart, Block, Borders, Cell, Clear, Gauge, List, ListItem, ListState, Paragraph, Row, Table, TableState},
     Frame,
 };
 
@@ -15,13 +17,29 @@ pub fn ui(frame: &mut Frame, app: &App) {
                 AppState::Input => render_input_state(frame, app),
                 AppState::Testing => render_testing_state(frame, app),
                 AppState::Results => render_results_state(frame, app),
+                AppState::PickInterface => {
+                    render_results_state(frame, app);
+                    render_interface_picker(frame, app);
+                }
+                AppState::SplitDns => render_split_dns_state(frame, app),
+                AppState::ApplyPreview => render_apply_preview(frame, app),
+                AppState::PickDistro | AppState::EditMirror => render_input_state(frame, app),
             }
         }
         AppMode::Mirror => {
             match app.state {
                 AppState::Input => render_mirror_input_state(frame, app),
+                AppState::PickDistro => {
+                    render_mirror_input_state(frame, app);
+                    render_distro_picker(frame, app);
+                }
+                AppState::EditMirror => {
+                    render_mirror_input_state(frame, app);
+                    render_mirror_form(frame, app);
+                }
                 AppState::Testing => render_testing_state(frame, app), // Sharing testing UI for now
-                AppState::Results => render_mirror_results_state(frame, app),
+                AppState::Results | AppState::PickInterface | AppState::SplitDns => render_mirror_results_state(frame, app),
+                AppState::ApplyPreview => render_apply_preview(frame, app),
             }
         }
     }
@@ -39,16 +57,35 @@ fn render_mirror_input_state(frame: &mut Frame, app: &App) {
         ])
         .split(frame.area());
 
-    let title = Paragraph::new(format!("🪞 Mirror Master - Distro: {} {}", app.detected_distro.emoji(), app.detected_distro.as_str()))
+    let system = &app.system;
+    let mut distro_label = format!("{} {}", app.detected_distro.emoji(), app.detected_distro.as_str());
+    if app.detected_distro != system.family {
+        distro_label.push_str(" (chosen)");
+    } else {
+        if let Some(version) = &system.version {
+            distro_label.push_str(&format!(" {}", version));
+        }
+        if let Some(codename) = &system.codename {
+            distro_label.push_str(&format!(" ({})", codename));
+        }
+        if Distro::from_id(&system.id) != system.family
+            && let Some(name) = &system.name
+        {
+            distro_label = format!("{} → {} mirrors", name, distro_label);
+        }
+    }
+    let title = Paragraph::new(format!("🪞 Mirror Master - Distro: {}", distro_label))
         .style(Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD))
         .block(Block::default().borders(Borders::ALL));
     frame.render_widget(title, chunks[0]);
 
     let mirror_items: Vec<ListItem> = app.mirrors.iter()
         .map(|m| {
+            let current = if m.origin == MirrorOrigin::Current { "📍 current " } else { "" };
             let content = Line::from(vec![
                 Span::styled(format!("{} {:<20}", m.distro.emoji(), m.name), Style::default().fg(Color::Yellow)),
                 Span::raw(" "),
+                Span::styled(current, Style::default().fg(Color::Cyan)),
                 Span::styled(&m.url, Style::default().fg(Color::DarkGray)),
             ]);
             ListItem::new(content)
@@ -56,20 +93,112 @@ fn render_mirror_input_state(frame: &mut Frame, app: &App) {
         .collect();
 
     let mirrors_list = List::new(mirror_items)
-        .block(Block::default().title("Mirrors to Test").borders(Borders::ALL))
-        .highlight_style(Style::default().add_modifier(Modifier::BOLD));
-    frame.render_widget(mirrors_list, chunks[1]);
-
-    let info = Paragraph::new(format!("📦 Loaded {} mirrors for {}. Use CSV to add more. ✨", app.mirrors.len(), app.detected_distro.as_str()))
-        .style(Style::default().fg(Color::Green))
-        .block(Block::default().borders(Borders::ALL));
+        .block(Block::default().title("Mirrors to Test (↑/↓)").borders(Borders::ALL))
+        .highlight_style(Style::default().bg(Color::DarkGray).add_modifier(Modifier::BOLD));
+    let mut list_state = ListState::default().with_selected((!app.mirrors.is_empty()).then_some(app.mirror_index));
+    frame.render_stateful_widget(mirrors_list, chunks[1], &mut list_state);
+
+    let info = match &app.status_message {
+        Some((msg, true)) => Paragraph::new(format!("❌ {}", msg)).style(Style::default().fg(Color::Red)),
+        Some((msg, false)) => Paragraph::new(format!("✅ {}", msg)).style(Style::default().fg(Color::Green)),
+        None => {
+            let sources: Vec<String> = app.loaded_mirror_sources().iter().map(|s| s.label()).collect();
+            Paragraph::new(format!(
+                "📦 Loaded {} mirrors for {} from {}. Press a to add more. ✨",
+                app.mirrors.len(),
+                app.detected_distro.as_str(),
+                sources.join(" + ")
+            ))
+            .style(Style::default().fg(Color::Green))
+        }
+    }
+    .block(Block::default().borders(Borders::ALL));
     frame.render_widget(info, chunks[2]);
 
-    let help = Paragraph::new("⌨️ Tab: Start Testing | 🖱️ m: Switch to DNS Mode | 🛑 q: Quit")
+    let help = Paragraph::new("⌨️ Tab: Start Testing | a: Add | Enter: Edit | x: Remove | t: Target distro | 🖱️ m: Switch to DNS Mode | 🛑 q: Quit")
         .style(Style::default().fg(Color::DarkGray));
     frame.render_widget(help, chunks[3]);
 }
 
+fn render_distro_picker(frame: &mut Frame, app: &App) {
+    let area = centered_rect(40, 60, frame.area());
+    frame.render_widget(Clear, area);
+
+    let items: Vec<ListItem> = Distro::TARGETS.iter()
+        .map(|d| {
+            let marker = if *d == app.system.family { " (detected)" } else { "" };
+            ListItem::new(format!("{} {}{}", d.emoji(), d.as_str(), marker)).style(Style::default().fg(Color::Yellow))
+        })
+        .collect();
+
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .title("🎯 Target distro (↑/↓, Enter: Select, Esc: Cancel)")
+                .borders(Borders::ALL)
+                .border_style(Style::default().fg(Color::Cyan)),
+        )
+        .highlight_style(Style::default().bg(Color::DarkGray).add_modifier(Modifier::BOLD));
+
+    let mut state = ListState::default().with_selected(Some(app.distro_index));
+    frame.render_stateful_widget(list, area, &mut state);
+}
+
+fn render_mirror_form(frame: &mut Frame, app: &App) {
+    let area = centered_rect(60, 50, frame.area());
+    frame.render_widget(Clear, area);
+
+    let form = &app.mirror_form;
+    let title = if form.editing.is_some() { "✏️ Edit mirror" } else { "➕ Add mirror" };
+    let outer = Block::default()
+        .title(format!("{} (Tab: Next field, Enter: Save, Esc: Cancel)", title))
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Cyan));
+    let inner = outer.inner(area);
+    frame.render_widget(outer, area);
+
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(1)
+        .constraints([
+            Constraint::Length(3), // Name
+            Constraint::Length(3), // URL
+            Constraint::Length(3), // Category
+            Constraint::Min(0),
+        ])
+        .split(inner);
+
+    let border = |field: MirrorField| {
+        let color = if form.field == field { Color::Yellow } else { Color::DarkGray };
+        Style::default().fg(color)
+    };
+    let inputs = [
+        (MirrorField::Name, "Name", &form.name, rows[0]),
+        (MirrorField::Url, "Base URL (repository root, e.g. https://mirror.example.com/debian)", &form.url, rows[1]),
+    ];
+    for (field, label, input, area) in inputs {
+        let width = area.width.saturating_sub(3) as usize;
+        let scroll = input.visual_scroll(width);
+        let widget = Paragraph::new(input.value())
+            .style(Style::default().fg(Color::White))
+            .scroll((0, scroll as u16))
+            .block(Block::default().title(label).borders(Borders::ALL).border_style(border(field)));
+        frame.render_widget(widget, area);
+        if form.field == field {
+            frame.set_cursor_position((
+                area.x + (input.visual_cursor().saturating_sub(scroll) as u16) + 1,
+                area.y + 1,
+            ));
+        }
+    }
+
+    let category = &Distro::CATEGORIES[form.category];
+    let category = Paragraph::new(format!("◀ {} {} ▶", category.emoji(), category.as_str()))
+        .style(Style::default().fg(Color::White))
+        .block(Block::default().title("Category (←/→)").borders(Borders::ALL).border_style(border(MirrorField::Category)));
+    frame.render_widget(category, rows[2]);
+}
+
 fn render_mirror_results_state(frame: &mut Frame, app: &App) {
     let chunks = Layout::default()
         .direction(Direction::Vertical)
@@ -87,8 +216,13 @@ fn render_mirror_results_state(frame: &mut Frame, app: &App) {
     frame.render_widget(title, chunks[0]);
 
     let header = Row::new(vec![
-        Cell::from("📋 Mirror Name").style(Style::default().fg(Color::Cyan)),
-        Cell::from("⚡ Speed (Mbps)").style(Style::default().fg(Color::Cyan)),
+        create_header_cell("📋 Mirror Name", SortColumn::Name, app),
+        create_header_cell("⚡ Speed (Mbps)", SortColumn::DownloadSpeed, app),
+        create_header_cell("⏱️ TTFB", SortColumn::Latency, app),
+        Cell::from("DNS").style(Style::default().fg(Color::Cyan)),
+        Cell::from("TCP").style(Style::default().fg(Color::Cyan)),
+        Cell::from("TLS").style(Style::default().fg(Color::Cyan)),
+        Cell::from("🕒 Lag").style(Style::default().fg(Color::Cyan)),
         Cell::from("🏷️ Status").style(Style::default().fg(Color::Cyan)),
     ]).height(1);
 
@@ -96,16 +230,53 @@ fn render_mirror_results_state(frame: &mut Frame, app: &App) {
         let speed_str = result.speed_mbps
             .map(|s| format!("{:.2}", s))
             .unwrap_or_else(|| "-".to_string());
+
+        let ms = |d: Option<std::time::Duration>| {
+            d.map(|d| format!("{}ms", d.as_millis())).unwrap_or_else(|| "-".to_string())
+        };
+        let timings = result.timings.as_ref();
+
+        let lag_str = result.lag.map(format_lag).unwrap_or_else(|| "-".to_string());
+        let lag_style = match result.lag {
+            _ if result.is_stale() => Style::default().fg(Color::Red),
+            Some(lag) if lag.as_secs() > 3600 => Style::default().fg(Color::Yellow),
+            _ => Style::default(),
+        };
         
         let (status, status_style) = if let Some(err) = &result.error {
             (format!("❌ {}", err), Style::default().fg(Color::Red))
+        } else if let Some(registry) = &result.registry {
+            let auth = registry
+                .auth
+                .map(|d| format!("{}ms", d.as_millis()))
+                .unwrap_or_else(|| "none".to_string());
+            (
+                format!("✅ auth {} | manifest {}ms", auth, registry.manifest.as_millis()),
+                Style::default().fg(Color::Green),
+            )
+        } else if let Some(t) = timings {
+            let mut summary = format!("{:.1} MiB", t.bytes as f64 / (1024.0 * 1024.0));
+            if result.stream_mbps.len() > 1 {
+                let per_stream = result.stream_mbps.iter().map(|s| format!("{:.1}", s)).collect::<Vec<_>>().join("/");
+                summary.push_str(&format!(", {} streams: {}", result.stream_mbps.len(), per_stream));
+            }
+            if t.range_ignored {
+                (format!("⚠️ {}, ignores Range", summary), Style::default().fg(Color::Yellow))
+            } else {
+                (format!("✅ {}", summary), Style::default().fg(Color::Green))
+            }
         } else {
             ("✅ OK".to_string(), Style::default().fg(Color::Green))
         };
 
         Row::new(vec![
-            Cell::from(result.name.clone()),
+            name_cell(result),
             Cell::from(speed_str),
+            Cell::from(ms(timings.map(|t| t.ttfb))),
+            Cell::from(ms(timings.map(|t| t.dns))),
+            Cell::from(ms(timings.map(|t| t.connect))),
+            Cell::from(ms(timings.and_then(|t| t.tls))),
+            Cell::from(lag_str).style(lag_style),
             Cell::from(status).style(status_style),
         ])
     });
@@ -113,9 +284,14 @@ fn render_mirror_results_state(frame: &mut Frame, app: &App) {
     let table = Table::new(
         rows,
         [
-            Constraint::Percentage(40),
-            Constraint::Percentage(20),
-            Constraint::Percentage(40),
+            Constraint::Percentage(22),
+            Constraint::Percentage(12),
+            Constraint::Percentage(9),
+            Constraint::Percentage(7),
+            Constraint::Percentage(7),
+            Constraint::Percentage(7),
+            Constraint::Percentage(8),
+            Constraint::Percentage(28),
         ],
     )
     .header(header)
@@ -124,11 +300,19 @@ fn render_mirror_results_state(frame: &mut Frame, app: &App) {
 
     frame.render_widget(table, chunks[1]);
 
-    let help = Paragraph::new("⌨️ r: New test | 🖱️ m: Switch Mode | 🛑 q: Quit")
-        .style(Style::default().fg(Color::DarkGray));
+    let help = status_or_help(app, "⌨️ s: Sort | d: Dir | a: Apply to system | D: Docker mirrors | u: pip/npm/cargo/Go | r: New test | 🖱️ m: Switch Mode | 🛑 q: Quit".to_string());
     frame.render_widget(help, chunks[2]);
 }
 
+/// Mirror name, with the currently configured mirrors marked as the baseline
+fn name_cell(result: &MirrorTestResult) -> Cell<'static> {
+    if result.origin == MirrorOrigin::Current {
+        Cell::from(format!("📍 {} (current)", result.name)).style(Style::default().fg(Color::Cyan))
+    } else {
+        Cell::from(result.name.clone())
+    }
+}
+
 fn render_input_state(frame: &mut Frame, app: &App) {
     let chunks = Layout::default()
         .direction(Direction::Vertical)
@@ -148,21 +332,44 @@ fn render_input_state(frame: &mut Frame, app: &App) {
         .block(Block::default().borders(Borders::ALL));
     frame.render_widget(title, chunks[0]);
 
-    // DNS server list
-    let items: Vec<ListItem> = app
-        .dns_servers
+    // DNS server list, narrowed by the tag filter
+    let targets = app.dns_targets();
+    let items: Vec<ListItem> = targets
         .iter()
         .enumerate()
         .map(|(i, ip)| {
-            ListItem::new(format!("{}. {}", i + 1, ip))
-                .style(Style::default().fg(Color::White))
+            let mut spans = vec![Span::styled(format!("{}. {:<16}", i + 1, ip), Style::default().fg(Color::White))];
+            if let Some(server) = app.dns_catalog.get(ip) {
+                if let Some(label) = server.label() {
+                    spans.push(Span::styled(format!(" {}", label), Style::default().fg(Color::Yellow)));
+                }
+                if !server.tags.is_empty() {
+                    spans.push(Span::styled(format!(" [{}]", server.tags.join(", ")), Style::default().fg(Color::Magenta)));
+                }
+                if let Some(protocol) = &server.protocol {
+                    spans.push(Span::styled(format!(" {}", protocol), Style::default().fg(Color::Cyan)));
+                }
+                if let Some(notes) = &server.notes {
+                    spans.push(Span::styled(format!(" · {}", notes), Style::default().fg(Color::DarkGray)));
+                }
+            }
+            ListItem::new(Line::from(spans))
         })
         .collect();
 
+    let mut list_title = if app.dns_sources.is_empty() {
+        "DNS Servers".to_string()
+    } else {
+        format!("DNS Servers ({})", app.dns_sources.join(" + "))
+    };
+    if let Some(tag) = &app.tag_filter {
+        list_title.push_str(&format!(" | tag: {} ({} of {})", tag, targets.len(), app.dns_servers.len()));
+    }
+
     let list = List::new(items)
         .block(
             Block::default()
-                .title("DNS Servers")
+                .title(list_title)
                 .borders(Borders::ALL)
                 .border_style(Style::default().fg(Color::Blue)),
         )
@@ -195,7 +402,7 @@ fn render_input_state(frame: &mut Frame, app: &App) {
     frame.render_widget(error, chunks[3]);
 
     // Help text
-    let help = Paragraph::new("⌨️ Enter: Add DNS | 🖱️ Backspace: Remove | 📑 Tab: Start test | 🛑 q: Quit")
+    let help = Paragraph::new("⌨️ Enter: Add DNS | 🖱️ Backspace: Remove | 🏷️ t: Tag filter | 📑 Tab: Start test | 🛑 q: Quit")
         .style(Style::default().fg(Color::DarkGray));
     frame.render_widget(help, chunks[4]);
 }
@@ -236,10 +443,11 @@ fn render_testing_state(frame: &mut Frame, app: &App) {
     frame.render_widget(title, chunks[0]);
 
     // Progress bar
-    let progress_ratio = if app.dns_servers.is_empty() {
+    let total = app.dns_targets().len();
+    let progress_ratio = if total == 0 {
         0.0
     } else {
-        app.testing_index as f64 / app.dns_servers.len() as f64
+        app.testing_index as f64 / total as f64
     };
 
     let label_color = if progress_ratio >= 0.5 {
@@ -254,7 +462,7 @@ fn render_testing_state(frame: &mut Frame, app: &App) {
         .percent((progress_ratio * 100.0) as u16)
         .label(
             Span::styled(
-                format!("{}/{}", app.testing_index, app.dns_servers.len()),
+                format!("{}/{}", app.testing_index, total),
                 Style::default().fg(label_color).add_modifier(Modifier::BOLD),
             )
         );
@@ -455,6 +663,7 @@ fn render_results_state(frame: &mut Frame, app: &App) {
     // Results table
     let header_cells = [
         create_header_cell("🖥️ DNS Server", SortColumn::Ip, app),
+        Cell::from("🏷️ Name"),
         create_header_cell("⏱️ Latency", SortColumn::Latency, app),
         create_header_cell("🚀 Download (Mbps)", SortColumn::DownloadSpeed, app),
         Cell::from("📋 Status"),
@@ -488,6 +697,7 @@ fn render_results_state(frame: &mut Frame, app: &App) {
 
         Row::new(vec![
             Cell::from(result.ip.to_string()),
+            Cell::from(app.dns_label(&result.ip).unwrap_or_else(|| "-".to_string())).style(Style::default().fg(Color::Yellow)),
             Cell::from(latency_str),
             Cell::from(speed_str),
             Cell::from(status).style(status_style),
@@ -498,6 +708,7 @@ fn render_results_state(frame: &mut Frame, app: &App) {
         rows,
         [
             Constraint::Length(20),
+            Constraint::Length(26),
             Constraint::Length(15),
             Constraint::Length(18),
             Constraint::Min(20),
@@ -510,30 +721,72 @@ fn render_results_state(frame: &mut Frame, app: &App) {
             .borders(Borders::ALL)
             .border_style(Style::default().fg(Color::Blue)),
     )
-    .style(Style::default().fg(Color::White));
+    .style(Style::default().fg(Color::White))
+    .row_highlight_style(Style::default().bg(Color::DarkGray));
 
-    frame.render_widget(table, chunks[1]);
+    let mut table_state = TableState::default().with_selected(Some(app.results_index));
+    frame.render_stateful_widget(table, chunks[1], &mut table_state);
 
     // Help & Status
-    let help_text = if let Some((msg, is_error)) = &app.status_message {
-        let prefix = if *is_error { "Error: " } else { "Success: " };
-        format!("{}{}", prefix, msg)
-    } else {
-        "s: Sort | d: Dir | r: New test | a: Apply Fastest | q: Quit".to_string()
-    };
-    
-    let help_style = if let Some((_, is_error)) = &app.status_message {
-        if *is_error {
-            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)
-        } else {
-            Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)
+    let target = app.selected_interface.as_deref().unwrap_or("auto");
+    let help = status_or_help(app, format!(
+        "s: Sort | d: Dir | r: New test | a: Apply Fastest | i: Interface ({}) | p: Split DNS | e: Export | q: Quit",
+        target
+    ));
+    frame.render_widget(help, chunks[2]);
+}
+
+/// The status message if there is one, otherwise the key help line
+fn status_or_help(app: &App, help_text: String) -> Paragraph<'static> {
+    match &app.status_message {
+        Some((msg, is_error)) => {
+            let (prefix, color) = if *is_error { ("Error: ", Color::Red) } else { ("Success: ", Color::Green) };
+            Paragraph::new(format!("{}{}", prefix, msg))
+                .style(Style::default().fg(color).add_modifier(Modifier::BOLD))
         }
-    } else {
-        Style::default().fg(Color::DarkGray)
-    };
+        None => Paragraph::new(help_text).style(Style::default().fg(Color::DarkGray)),
+    }
+}
 
-    let help = Paragraph::new(help_text)
-        .style(help_style);
+fn render_apply_preview(frame: &mut Frame, app: &App) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(2)
+        .constraints([
+            Constraint::Length(3), // Title
+            Constraint::Min(5),    // Diff
+            Constraint::Length(2), // Help
+        ])
+        .split(frame.area());
+
+    let title = Paragraph::new("📝 Review Mirror Changes 📝")
+        .style(Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD))
+        .block(Block::default().borders(Borders::ALL));
+    frame.render_widget(title, chunks[0]);
+
+    let mut lines = Vec::new();
+    for (change, diff) in app.pending_changes.iter().zip(&app.pending_diffs) {
+        lines.push(Line::from(Span::styled(
+            format!("--- {}", change.path.display()),
+            Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD),
+        )));
+        for line in diff {
+            lines.push(match line {
+                DiffLine::Same(l) => Line::from(Span::styled(format!("  {}", l), Style::default().fg(Color::DarkGray))),
+                DiffLine::Added(l) => Line::from(Span::styled(format!("+ {}", l), Style::default().fg(Color::Green))),
+                DiffLine::Removed(l) => Line::from(Span::styled(format!("- {}", l), Style::default().fg(Color::Red))),
+            });
+        }
+        lines.push(Line::from(""));
+    }
+
+    let diff = Paragraph::new(lines)
+        .scroll((app.preview_scroll, 0))
+        .block(Block::default().title("Diff (↑/↓ to scroll)").borders(Borders::ALL).border_style(Style::default().fg(Color::Blue)));
+    frame.render_widget(diff, chunks[1]);
+
+    let help = Paragraph::new("y: Write files (a timestamped backup is kept) | n/Esc: Cancel")
+        .style(Style::default().fg(Color::Yellow));
     frame.render_widget(help, chunks[2]);
 }
 
@@ -555,3 +808,140 @@ fn create_header_cell<'a>(text: &'a str, column: SortColumn, app: &App) -> Cell<
 
     Cell::from(format!("{}{}", text, arrow)).style(style)
 }
+
+fn render_split_dns_state(frame: &mut Frame, app: &App) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(2)
+        .constraints([
+            Constraint::Length(3), // Title
+            Constraint::Min(5),    // Resolvers | Routes
+            Constraint::Length(3), // Input
+            Constraint::Length(2), // Status
+            Constraint::Length(2), // Help
+        ])
+        .split(frame.area());
+
+    let title = Paragraph::new("🔀 Split DNS Routing 🔀")
+        .style(Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD))
+        .block(Block::default().borders(Borders::ALL));
+    frame.render_widget(title, chunks[0]);
+
+    let panes = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(45), Constraint::Percentage(55)])
+        .split(chunks[1]);
+
+    // Resolvers to pick from, in results order
+    let resolvers: Vec<ListItem> = app.results.iter()
+        .map(|r| {
+            let latency = r.latency
+                .map(|d| format!("{:.0}ms", d.as_secs_f64() * 1000.0))
+                .unwrap_or_else(|| "-".to_string());
+            let style = if r.error.is_some() { Style::default().fg(Color::Red) } else { Style::default().fg(Color::White) };
+            ListItem::new(format!("{:<18} {}", r.ip, latency)).style(style)
+        })
+        .collect();
+    let resolvers_list = List::new(resolvers)
+        .block(Block::default().title("Resolvers (↑/↓)").borders(Borders::ALL).border_style(Style::default().fg(Color::Blue)))
+        .highlight_style(Style::default().bg(Color::DarkGray).add_modifier(Modifier::BOLD));
+    let mut list_state = ListState::default().with_selected(Some(app.results_index));
+    frame.render_stateful_widget(resolvers_list, panes[0], &mut list_state);
+
+    // Current routing table
+    let mut routes: Vec<ListItem> = app.split_dns.routes.iter()
+        .map(|r| {
+            let link = r.link.as_deref().map(|l| format!(" via {}", l)).unwrap_or_default();
+            ListItem::new(Line::from(vec![
+                Span::styled(format!("~{}", r.domain), Style::default().fg(Color::Yellow)),
+                Span::raw(format!(" → {}{}", r.server, link)),
+            ]))
+        })
+        .collect();
+    let default = app.split_dns.default
+        .map(|ip| ip.to_string())
+        .unwrap_or_else(|| "(unchanged)".to_string());
+    routes.push(ListItem::new(Line::from(vec![
+        Span::styled("everything else", Style::default().fg(Color::Green)),
+        Span::raw(format!(" → {}", default)),
+    ])));
+    let routes_list = List::new(routes)
+        .block(Block::default().title("Routes").borders(Borders::ALL).border_style(Style::default().fg(Color::Blue)));
+    frame.render_widget(routes_list, panes[1]);
+
+    // Domain input
+    let width = chunks[2].width.saturating_sub(3) as usize;
+    let scroll = app.input.visual_scroll(width);
+    let input = Paragraph::new(app.input.value())
+        .style(Style::default().fg(Color::Yellow))
+        .scroll((0, scroll as u16))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("✍️ Domain suffix, optionally @link (e.g. corp.example@tun0)")
+                .border_style(Style::default().fg(Color::Yellow)),
+        );
+    frame.render_widget(input, chunks[2]);
+    frame.set_cursor_position((
+        chunks[2].x + (app.input.visual_cursor().saturating_sub(scroll) as u16) + 1,
+        chunks[2].y + 1,
+    ));
+
+    if let Some((msg, is_error)) = &app.status_message {
+        let color = if *is_error { Color::Red } else { Color::Green };
+        let status = Paragraph::new(msg.as_str()).style(Style::default().fg(color).add_modifier(Modifier::BOLD));
+        frame.render_widget(status, chunks[3]);
+    }
+
+    let help = Paragraph::new("Enter: Route domain to resolver (empty: set default) | Backspace: Remove last | Tab: Apply | Esc: Back")
+        .style(Style::default().fg(Color::DarkGray));
+    frame.render_widget(help, chunks[4]);
+}
+
+fn render_interface_picker(frame: &mut Frame, app: &App) {
+    let area = centered_rect(60, 50, frame.area());
+    frame.render_widget(Clear, area);
+
+    let items: Vec<ListItem> = app.interfaces.iter()
+        .map(|t| {
+            let marker = if app.selected_interface.as_deref() == Some(t.name.as_str()) { "✔ " } else { "  " };
+            ListItem::new(Line::from(vec![
+                Span::styled(format!("{}{:<24}", marker, t.name), Style::default().fg(Color::Yellow)),
+                Span::styled(t.description.clone(), Style::default().fg(Color::DarkGray)),
+            ]))
+        })
+        .collect();
+
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .title("🔌 Apply DNS to (↑/↓, Enter: Select, Esc: Cancel)")
+                .borders(Borders::ALL)
+                .border_style(Style::default().fg(Color::Cyan)),
+        )
+        .highlight_style(Style::default().bg(Color::DarkGray).add_modifier(Modifier::BOLD));
+
+    let mut state = ListState::default().with_selected(Some(app.interface_index));
+    frame.render_stateful_widget(list, area, &mut state);
+}
+
+/// A rectangle of `percent_x` by `percent_y` centered in `area`
+fn centered_rect(percent_x: u16, percent_y: u16, area: Rect) -> Rect {
+    let vertical = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Percentage((100 - percent_y) / 2),
+            Constraint::Percentage(percent_y),
+            Constraint::Percentage((100 - percent_y) / 2),
+        ])
+        .split(area);
+
+    Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Percentage((100 - percent_x) / 2),
+            Constraint::Percentage(percent_x),
+            Constraint::Percentage((100 - percent_x) / 2),
+        ])
+        .split(vertical[1])[1]
+}
@@ -0,0 +1,117 @@
+use anyhow::{anyhow, Result};
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::dns_utils::{self, BenchmarkState};
+use crate::sys_dns;
+
+/// Settings for the failover watchdog.
+#[derive(Debug, Clone)]
+pub struct WatchdogConfig {
+    pub interval: Duration,
+    /// Consecutive failed probes before switching
+    pub max_failures: u32,
+    /// Probes slower than this count as failures
+    pub max_latency: Duration,
+    /// Resolver to start from; defaults to the last one applied
+    pub current: Option<IpAddr>,
+    pub interface: Option<String>,
+}
+
+fn log(message: &str) {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (h, m, s) = ((secs / 3600) % 24, (secs / 60) % 60, secs % 60);
+    println!("[{:02}:{:02}:{:02} UTC] {}", h, m, s, message);
+}
+
+/// Why a probe of the current resolver counts as a failure, if it does.
+fn probe_failure(result: &dns_utils::DnsTestResult, max_latency: Duration) -> Option<String> {
+    if let Some(err) = &result.error {
+        return Some(err.clone());
+    }
+    match result.latency {
+        Some(latency) if latency > max_latency => Some(format!(
+            "latency {:.0}ms is over the {}ms threshold",
+            latency.as_secs_f64() * 1000.0,
+            max_latency.as_millis()
+        )),
+        Some(_) => None,
+        None => Some("no latency measured".to_string()),
+    }
+}
+
+/// Switch the system to the next resolver after `current` in the ranking that
+/// passes a probe and can be applied. Returns the one switched to, if any.
+async fn switch_to_next(
+    ranking: &[IpAddr],
+    current: IpAddr,
+    max_latency: Duration,
+    interface: Option<&str>,
+) -> Option<IpAddr> {
+    let start = ranking.iter().position(|ip| *ip == current).map(|i| i + 1).unwrap_or(0);
+    let candidates = ranking[start..].iter().chain(ranking[..start].iter()).filter(|ip| **ip != current);
+    for ip in candidates {
+        let result = dns_utils::run_full_test(*ip).await;
+        if let Some(reason) = probe_failure(&result, max_latency) {
+            log(&format!("Skipping {}: {}", ip, reason));
+            continue;
+        }
+
+        let (next, interface) = (*ip, interface.map(str::to_string));
+        let applied = tokio::task::spawn_blocking(move || sys_dns::set_system_dns(next, interface.as_deref()))
+            .await
+            .unwrap_or_else(|e| Err(anyhow!("DNS switch task panicked: {}", e)));
+        match applied {
+            Ok(_) => return Some(next),
+            Err(e) => log(&format!("Failed to switch system DNS to {}: {:#}", next, e)),
+        }
+    }
+    None
+}
+
+/// Probe the applied resolver forever, switching to the next-best one from the
+/// last benchmark when it keeps failing or gets too slow.
+pub async fn run(config: WatchdogConfig) -> Result<()> {
+    let state = BenchmarkState::load();
+    let ranking = dns_utils::ranked_servers(&state.results);
+    if ranking.is_empty() {
+        anyhow::bail!("No saved benchmark. Run a DNS test first (TUI or `export`).");
+    }
+    let mut current = config.current.or(state.applied).unwrap_or(ranking[0]);
+    let mut failures = 0;
+
+    log(&format!(
+        "Watching {} every {}s ({} resolvers ranked, switch after {} failures)",
+        current, config.interval.as_secs(), ranking.len(), config.max_failures
+    ));
+
+    loop {
+        let result = dns_utils::run_full_test(current).await;
+        match probe_failure(&result, config.max_latency) {
+            None => {
+                if failures > 0 {
+                    log(&format!("{} recovered", current));
+                }
+                failures = 0;
+            }
+            Some(reason) => {
+                failures += 1;
+                log(&format!("{} failed ({}/{}): {}", current, failures, config.max_failures, reason));
+            }
+        }
+
+        if failures >= config.max_failures {
+            match switch_to_next(&ranking, current, config.max_latency, config.interface.as_deref()).await {
+                Some(next) => {
+                    log(&format!("Switched system DNS from {} to {}", current, next));
+                    let _ = BenchmarkState::record_applied(next);
+                    current = next;
+                    failures = 0;
+                }
+                None => log("No other resolver from the last benchmark could be switched to; keeping the current one"),
+            }
+        }
+
+        tokio::time::sleep(config.interval).await;
+    }
+}